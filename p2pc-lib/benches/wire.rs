@@ -0,0 +1,87 @@
+// Compares size and speed of the JSON and CBOR encodings of wire messages.
+//
+// Run with `cargo bench -p p2pc-lib --features bench`, the sizes are printed before the
+// measurements.
+
+use p2pc_lib::wire::{self, Encoding, Envelope, WireMessage};
+
+fn participants(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            libp2p::identity::Keypair::generate_ed25519()
+                .public()
+                .to_peer_id()
+                .to_string()
+        })
+        .collect()
+}
+
+fn chat_message(attachment: Option<p2pc_lib::Attachment>) -> Envelope {
+    let chat_message = p2pc_lib::ChatMessage {
+        participants: participants(3),
+        content: "Are we still meeting at the station at eight? I'll bring the tickets."
+            .to_string(),
+        id: uuid::Uuid::new_v4(),
+        chat_id: uuid::Uuid::new_v4(),
+        answer_to: Some(uuid::Uuid::new_v4()),
+        sent_at: std::time::SystemTime::now(),
+        attachment,
+    };
+    Envelope::new(WireMessage::ChatMessage(
+        wire::EncryptedChatMessage::encrypt(chat_message, &libp2p::PeerId::random()).unwrap(),
+    ))
+}
+
+fn messages() -> Vec<(&'static str, Envelope)> {
+    let attachment = p2pc_lib::Attachment {
+        file_id: uuid::Uuid::new_v4(),
+        name: "holiday.jpg".to_string(),
+        size: 3_481_920,
+        sha256: vec![0xab; 32],
+        thumbnail: (0..12_000).map(|index| (index * 7 % 251) as u8).collect(),
+    };
+    vec![
+        ("chat message", chat_message(None)),
+        (
+            "chat message with thumbnail",
+            chat_message(Some(attachment)),
+        ),
+        (
+            "typing",
+            Envelope::new(WireMessage::Typing(wire::Typing {
+                chat_id: uuid::Uuid::new_v4(),
+                sent_at: std::time::SystemTime::now(),
+            })),
+        ),
+    ]
+}
+
+fn encodings(criterion: &mut criterion::Criterion) {
+    for (name, envelope) in messages() {
+        for encoding in [Encoding::Json, Encoding::Cbor] {
+            let bytes = encoding.encode(&envelope).unwrap();
+            println!("{name} as {encoding:?}: {} bytes", bytes.len());
+            // unknown kinds are skipped silently, so make sure the message survives the round trip
+            assert!(matches!(
+                encoding.decode_envelope(&bytes).unwrap().content,
+                wire::EnvelopeContent::Known(_)
+            ));
+
+            let mut group = criterion.benchmark_group(format!("{name}/{encoding:?}"));
+            group.bench_function("encode", |bencher| {
+                bencher.iter(|| encoding.encode(criterion::black_box(&envelope)).unwrap())
+            });
+            group.bench_function("decode", |bencher| {
+                bencher.iter(|| {
+                    encoding
+                        .decode_envelope(criterion::black_box(&bytes))
+                        .unwrap()
+                })
+            });
+            group.finish();
+        }
+    }
+}
+
+criterion::criterion_group!(benches, encodings);
+criterion::criterion_main!(benches);
@@ -0,0 +1,98 @@
+// Codec of the messages sent directly to a peer.
+//
+// The encoding is negotiated with the protocol when a stream is opened. We offer CBOR first and
+// fall back to JSON for peers that don't support it yet.
+
+use libp2p::futures::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::wire;
+
+pub const CBOR_PROTOCOL: libp2p::StreamProtocol =
+    libp2p::StreamProtocol::new("/p2pc/message/2.0.0");
+pub const JSON_PROTOCOL: libp2p::StreamProtocol =
+    libp2p::StreamProtocol::new("/p2pc/message/1.0.0");
+/// Protocols in the order we prefer them.
+pub const PROTOCOLS: [libp2p::StreamProtocol; 2] = [CBOR_PROTOCOL, JSON_PROTOCOL];
+
+const REQUEST_SIZE_MAXIMUM: u64 = 1024 * 1024;
+const RESPONSE_SIZE_MAXIMUM: u64 = 1024;
+
+#[derive(Clone, Default)]
+pub struct Codec;
+
+fn encoding(protocol: &libp2p::StreamProtocol) -> wire::Encoding {
+    if *protocol == CBOR_PROTOCOL {
+        wire::Encoding::Cbor
+    } else {
+        wire::Encoding::Json
+    }
+}
+
+#[async_trait::async_trait]
+impl libp2p::request_response::Codec for Codec {
+    type Protocol = libp2p::StreamProtocol;
+    type Request = wire::Envelope;
+    type Response = ();
+
+    async fn read_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Request>
+    where
+        T: libp2p::futures::AsyncRead + Unpin + Send,
+    {
+        let mut bytes = vec![];
+        io.take(REQUEST_SIZE_MAXIMUM)
+            .read_to_end(&mut bytes)
+            .await?;
+        encoding(protocol)
+            .decode_envelope(&bytes)
+            .map_err(invalid_data)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Response>
+    where
+        T: libp2p::futures::AsyncRead + Unpin + Send,
+    {
+        let mut bytes = vec![];
+        io.take(RESPONSE_SIZE_MAXIMUM)
+            .read_to_end(&mut bytes)
+            .await?;
+        encoding(protocol).decode(&bytes).map_err(invalid_data)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> std::io::Result<()>
+    where
+        T: libp2p::futures::AsyncWrite + Unpin + Send,
+    {
+        let bytes = encoding(protocol).encode(&request).map_err(invalid_data)?;
+        io.write_all(&bytes).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> std::io::Result<()>
+    where
+        T: libp2p::futures::AsyncWrite + Unpin + Send,
+    {
+        let bytes = encoding(protocol).encode(&response).map_err(invalid_data)?;
+        io.write_all(&bytes).await
+    }
+}
+
+fn invalid_data(error: anyhow::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,250 @@
+// End-to-end encryption of payloads for a set of peers.
+//
+// The payload is encrypted with a random content key. The content key is wrapped once per
+// recipient with a key derived from an X25519 key agreement between a per-payload ephemeral key
+// and the recipient's identity key. Peer ids of ed25519 identities embed the public key, so the
+// recipients can be addressed by their peer id alone. For the same reason signatures can be
+// verified knowing only the peer id of the signer.
+//
+// The plaintext fields sent along with a payload are authenticated as associated data, so a
+// payload cannot be replayed with other fields, e.g. into another chat.
+
+use std::collections::HashMap;
+use std::str::FromStr as _;
+
+use anyhow::Context as _;
+use chacha20poly1305::aead::{Aead as _, AeadCore as _, KeyInit as _, OsRng, Payload};
+use sha2::Digest as _;
+
+/// A payload that only the listed recipients can decrypt.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Sealed {
+    #[serde(with = "crate::serde_base64")]
+    ephemeral_public_key: Vec<u8>,
+    #[serde(with = "crate::serde_base64")]
+    nonce: Vec<u8>,
+    #[serde(with = "crate::serde_base64")]
+    ciphertext: Vec<u8>,
+    /// Wrapped content key for every recipient, keyed by peer id.
+    keys: HashMap<String, WrappedKey>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct WrappedKey(#[serde(with = "crate::serde_base64")] Vec<u8>);
+
+pub fn seal(
+    plaintext: &[u8],
+    associated_data: &[u8],
+    recipients: &[String],
+) -> anyhow::Result<Sealed> {
+    let content_key = chacha20poly1305::ChaCha20Poly1305::generate_key(&mut OsRng);
+    let nonce = chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = chacha20poly1305::ChaCha20Poly1305::new(&content_key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: associated_data,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("could not encrypt payload"))?;
+
+    let ephemeral_secret = x25519_dalek::StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public_key = x25519_dalek::PublicKey::from(&ephemeral_secret);
+
+    let keys = recipients
+        .iter()
+        .map(|recipient| {
+            let recipient_public_key = x25519_public_key(recipient)?;
+            let wrapped_key = key_wrapping_cipher(
+                &ephemeral_secret.diffie_hellman(&recipient_public_key),
+                &ephemeral_public_key,
+                &recipient_public_key,
+            )
+            // every wrapping key is only used once, so a constant nonce is fine
+            .encrypt(&chacha20poly1305::Nonce::default(), content_key.as_slice())
+            .map_err(|_| anyhow::anyhow!("could not wrap content key for {recipient}"))?;
+            Ok((recipient.clone(), WrappedKey(wrapped_key)))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Sealed {
+        ephemeral_public_key: ephemeral_public_key.as_bytes().to_vec(),
+        nonce: nonce.to_vec(),
+        ciphertext,
+        keys,
+    })
+}
+
+/// Decrypts a payload, failing if `associated_data` differs from the one it was sealed with.
+pub fn open(
+    sealed: &Sealed,
+    associated_data: &[u8],
+    keypair: &libp2p::identity::Keypair,
+) -> anyhow::Result<Vec<u8>> {
+    let local_peer_id = keypair.public().to_peer_id().to_string();
+    let WrappedKey(wrapped_key) = sealed
+        .keys
+        .get(&local_peer_id)
+        .context("payload is not encrypted for this peer")?;
+
+    let ephemeral_public_key = x25519_dalek::PublicKey::from(
+        <[u8; 32]>::try_from(sealed.ephemeral_public_key.as_slice())
+            .context("invalid ephemeral public key")?,
+    );
+    let local_secret = x25519_secret(keypair)?;
+    let content_key = key_wrapping_cipher(
+        &local_secret.diffie_hellman(&ephemeral_public_key),
+        &ephemeral_public_key,
+        &x25519_dalek::PublicKey::from(&local_secret),
+    )
+    .decrypt(&chacha20poly1305::Nonce::default(), wrapped_key.as_slice())
+    .map_err(|_| anyhow::anyhow!("could not unwrap content key"))?;
+
+    anyhow::ensure!(
+        content_key.len() == 32 && sealed.nonce.len() == 12,
+        "invalid content key or nonce"
+    );
+    chacha20poly1305::ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&content_key))
+        .decrypt(
+            chacha20poly1305::Nonce::from_slice(&sealed.nonce),
+            Payload {
+                msg: &sealed.ciphertext,
+                aad: associated_data,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("could not decrypt payload"))
+}
+
+pub fn sign(payload: &[u8], keypair: &libp2p::identity::Keypair) -> anyhow::Result<Vec<u8>> {
+    Ok(keypair.sign(payload)?)
+}
+
+pub fn verify(payload: &[u8], signature: &[u8], signer: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        public_key(signer)?.verify(payload, signature),
+        "invalid signature of {signer}"
+    );
+    Ok(())
+}
+
+fn key_wrapping_cipher(
+    shared_secret: &x25519_dalek::SharedSecret,
+    ephemeral_public_key: &x25519_dalek::PublicKey,
+    recipient_public_key: &x25519_dalek::PublicKey,
+) -> chacha20poly1305::ChaCha20Poly1305 {
+    let salt = [
+        ephemeral_public_key.as_bytes().as_slice(),
+        recipient_public_key.as_bytes().as_slice(),
+    ]
+    .concat();
+    let mut key = chacha20poly1305::Key::default();
+    hkdf::Hkdf::<sha2::Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(b"p2pc key wrapping", &mut key)
+        .expect("32 bytes is a valid hkdf output length");
+    chacha20poly1305::ChaCha20Poly1305::new(&key)
+}
+
+/// Converts an ed25519 identity secret into its X25519 form, the same way libsodium does.
+fn x25519_secret(
+    keypair: &libp2p::identity::Keypair,
+) -> anyhow::Result<x25519_dalek::StaticSecret> {
+    let keypair = keypair
+        .clone()
+        .try_into_ed25519()
+        .context("only ed25519 identities support encryption")?;
+    let hash = sha2::Sha512::digest(keypair.secret().as_ref());
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    Ok(x25519_dalek::StaticSecret::from(scalar))
+}
+
+/// Extracts the public key embedded in a peer id.
+fn public_key(peer_id: &str) -> anyhow::Result<libp2p::identity::PublicKey> {
+    let peer_id = libp2p::PeerId::from_str(peer_id)?;
+    let multihash: &libp2p::multihash::Multihash<64> = peer_id.as_ref();
+    // peer ids of small keys use the identity multihash, i.e. contain the key itself
+    anyhow::ensure!(
+        multihash.code() == 0,
+        "peer id {peer_id} does not embed its public key"
+    );
+    Ok(libp2p::identity::PublicKey::try_decode_protobuf(
+        multihash.digest(),
+    )?)
+}
+
+/// Extracts the ed25519 public key embedded in a peer id and converts it into its X25519 form.
+fn x25519_public_key(peer_id: &str) -> anyhow::Result<x25519_dalek::PublicKey> {
+    let public_key = public_key(peer_id)?
+        .try_into_ed25519()
+        .context("only ed25519 identities support encryption")?;
+    let montgomery_point = curve25519_dalek::edwards::CompressedEdwardsY(public_key.to_bytes())
+        .decompress()
+        .context("invalid ed25519 public key")?
+        .to_montgomery();
+    Ok(x25519_dalek::PublicKey::from(montgomery_point.to_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(keypair: &libp2p::identity::Keypair) -> String {
+        keypair.public().to_peer_id().to_string()
+    }
+
+    #[test]
+    fn recipients_open_sealed_payload() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let bob = libp2p::identity::Keypair::generate_ed25519();
+        let sealed = seal(b"hello", b"chat", &[peer(&alice), peer(&bob)]).unwrap();
+        assert_eq!(open(&sealed, b"chat", &alice).unwrap(), b"hello");
+        assert_eq!(open(&sealed, b"chat", &bob).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn other_peers_cannot_open_sealed_payload() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let mallory = libp2p::identity::Keypair::generate_ed25519();
+        let sealed = seal(b"hello", b"chat", &[peer(&alice)]).unwrap();
+        assert!(open(&sealed, b"chat", &mallory).is_err());
+
+        // a key wrapped for someone else does not open the payload either
+        let mut stolen = sealed.clone();
+        let wrapped_key = stolen.keys.remove(&peer(&alice)).unwrap();
+        stolen.keys.insert(peer(&mallory), wrapped_key);
+        assert!(open(&stolen, b"chat", &mallory).is_err());
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let sealed = seal(b"hello", b"chat", &[peer(&alice)]).unwrap();
+
+        let mut tampered = sealed.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(open(&tampered, b"chat", &alice).is_err());
+
+        let mut tampered = sealed.clone();
+        tampered.nonce[0] ^= 1;
+        assert!(open(&tampered, b"chat", &alice).is_err());
+    }
+
+    #[test]
+    fn other_associated_data_is_rejected() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let sealed = seal(b"hello", b"chat", &[peer(&alice)]).unwrap();
+        assert!(open(&sealed, b"other chat", &alice).is_err());
+        assert!(open(&sealed, b"", &alice).is_err());
+    }
+
+    #[test]
+    fn signatures_verify_with_peer_id() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let bob = libp2p::identity::Keypair::generate_ed25519();
+        let signature = sign(b"payload", &alice).unwrap();
+        assert!(verify(b"payload", &signature, &peer(&alice)).is_ok());
+        assert!(verify(b"other payload", &signature, &peer(&alice)).is_err());
+        assert!(verify(b"payload", &signature, &peer(&bob)).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+// Transfer of files attached to chat messages.
+//
+// The recipient pulls the file from its sender chunk by chunk and appends the chunks to a partial
+// file next to the destination, so an interrupted download resumes where it stopped. The partial
+// file is named after the id and size of the attachment, so it is only resumed by a download of
+// the same file. Once the file is complete its hash is checked against the one in the attachment.
+// Chunks are only served to the peers a file was shared with, the connection itself is encrypted
+// by the transport.
+
+use std::io::{Read as _, Seek as _, Write as _};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use sha2::Digest as _;
+
+pub const PROTOCOL: libp2p::StreamProtocol = libp2p::StreamProtocol::new("/p2pc/file/1.0.0");
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Reference to a file attached to a chat message.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub file_id: uuid::Uuid,
+    pub name: String,
+    pub size: u64,
+    #[serde(with = "crate::serde_base64")]
+    pub sha256: Vec<u8>,
+    /// Small preview of image files, encoded in any common image format. Empty for other files.
+    #[serde(default, with = "crate::serde_base64")]
+    pub thumbnail: Vec<u8>,
+}
+
+impl Attachment {
+    /// Describes the file at `path`. Reads the whole file to hash it.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let name = path
+            .file_name()
+            .context("path does not point to a file")?
+            .to_string_lossy()
+            .to_string();
+        let (size, sha256) = hash_file(path)?;
+        Ok(Self {
+            file_id: uuid::Uuid::new_v4(),
+            name,
+            size,
+            sha256,
+            thumbnail: vec![],
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ChunkRequest {
+    pub file_id: uuid::Uuid,
+    pub offset: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub enum ChunkResponse {
+    Chunk(#[serde(with = "crate::serde_base64")] Vec<u8>),
+    /// The file is not shared with the requesting peer or cannot be read anymore.
+    Unavailable,
+}
+
+/// A file we offer to the participants of a chat.
+#[derive(Clone)]
+pub struct SharedFile {
+    pub path: PathBuf,
+    pub peers: Vec<String>,
+}
+
+impl SharedFile {
+    pub fn read_chunk(&self, offset: u64) -> std::io::Result<Vec<u8>> {
+        let mut file = std::fs::File::open(&self.path)?;
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        let mut chunk = vec![];
+        file.take(CHUNK_SIZE).read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+}
+
+#[derive(Clone)]
+pub struct Download {
+    pub peer: libp2p::PeerId,
+    pub attachment: Attachment,
+    pub path: PathBuf,
+    received: u64,
+}
+
+impl Download {
+    /// Continues a previous download of the attachment into the directory of `path` if its
+    /// partial file is still there.
+    pub fn new(
+        peer: libp2p::PeerId,
+        attachment: Attachment,
+        path: PathBuf,
+    ) -> std::io::Result<Self> {
+        let mut download = Self {
+            peer,
+            attachment,
+            path,
+            received: 0,
+        };
+        let part_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(download.part_path())?;
+        download.received = part_file.metadata()?.len();
+        if download.received > download.attachment.size {
+            part_file.set_len(0)?;
+            download.received = 0;
+        }
+        Ok(download)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.attachment.size
+    }
+
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(!chunk.is_empty(), "file ended unexpectedly");
+        anyhow::ensure!(
+            self.received + chunk.len() as u64 <= self.attachment.size,
+            "file is larger than announced"
+        );
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(self.part_path())?
+            .write_all(chunk)?;
+        self.received += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Verifies the hash of the complete file and moves it to its destination, which must not
+    /// exist yet.
+    pub fn finish(&self) -> anyhow::Result<()> {
+        let (_, sha256) = hash_file(&self.part_path())?;
+        if sha256 != self.attachment.sha256 {
+            std::fs::remove_file(self.part_path())?;
+            anyhow::bail!("hash of the received file does not match");
+        }
+        anyhow::ensure!(!self.path.exists(), "{:?} exists already", self.path);
+        std::fs::rename(self.part_path(), &self.path)?;
+        Ok(())
+    }
+
+    pub fn next_request(&self) -> ChunkRequest {
+        ChunkRequest {
+            file_id: self.attachment.file_id,
+            offset: self.received,
+        }
+    }
+
+    fn part_path(&self) -> PathBuf {
+        self.path.with_file_name(format!(
+            ".{}-{}.part",
+            self.attachment.file_id, self.attachment.size
+        ))
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<(u64, Vec<u8>)> {
+    let mut hasher = sha2::Sha256::new();
+    let size = std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok((size, hasher.finalize().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory that is removed again when dropped.
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("p2pc-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn attachment(content: &[u8]) -> Attachment {
+        Attachment {
+            file_id: uuid::Uuid::new_v4(),
+            name: "file.txt".to_string(),
+            size: content.len() as u64,
+            sha256: sha2::Sha256::digest(content).to_vec(),
+            thumbnail: vec![],
+        }
+    }
+
+    fn download(attachment: &Attachment, path: &Path) -> Download {
+        Download::new(
+            libp2p::PeerId::random(),
+            attachment.clone(),
+            path.to_owned(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn interrupted_download_is_resumed() {
+        let directory = Directory::new();
+        let path = directory.0.join("file.txt");
+        let attachment = attachment(b"hello world");
+
+        download(&attachment, &path).write_chunk(b"hello").unwrap();
+
+        // the destination may change, the partial file belongs to the attachment
+        let path = directory.0.join("(1) file.txt");
+        let mut resumed = download(&attachment, &path);
+        assert_eq!(resumed.received(), 5);
+        resumed.write_chunk(b" world").unwrap();
+        assert!(resumed.is_complete());
+        resumed.finish().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!resumed.part_path().exists());
+    }
+
+    #[test]
+    fn partial_files_of_other_attachments_are_not_resumed() {
+        let directory = Directory::new();
+        let path = directory.0.join("file.txt");
+        let first = attachment(b"hello world");
+        download(&first, &path).write_chunk(b"hello").unwrap();
+
+        // same name, other file
+        assert_eq!(download(&attachment(b"hello world"), &path).received(), 0);
+
+        // same id, other size
+        let mut resized = first.clone();
+        resized.size += 1;
+        assert_eq!(download(&resized, &path).received(), 0);
+
+        assert_eq!(download(&first, &path).received(), 5);
+    }
+
+    #[test]
+    fn download_with_wrong_hash_is_discarded() {
+        let directory = Directory::new();
+        let path = directory.0.join("file.txt");
+        let attachment = attachment(b"hello world");
+
+        let mut download = download(&attachment, &path);
+        download.write_chunk(b"hello there").unwrap();
+        assert!(download.is_complete());
+        assert!(download.finish().is_err());
+        assert!(!path.exists());
+        assert!(!download.part_path().exists());
+    }
+
+    #[test]
+    fn chunks_beyond_the_announced_size_are_rejected() {
+        let directory = Directory::new();
+        let mut download = download(&attachment(b"hello"), &directory.0.join("file.txt"));
+        assert!(download.write_chunk(b"hello world").is_err());
+        assert!(download.write_chunk(b"").is_err());
+        assert_eq!(download.received(), 0);
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let directory = Directory::new();
+        let path = directory.0.join("file.txt");
+        std::fs::write(&path, b"mine").unwrap();
+
+        let mut download = download(&attachment(b"hello"), &path);
+        download.write_chunk(b"hello").unwrap();
+        assert!(download.finish().is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"mine");
+        // the partial file is kept, a download to another path picks it up
+        assert!(download.part_path().exists());
+    }
+}
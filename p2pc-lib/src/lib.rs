@@ -1,23 +1,168 @@
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash as _, Hasher as _};
+use std::str::FromStr as _;
 
 use libp2p::futures::StreamExt as _;
 
-#[derive(serde::Serialize, serde::Deserialize)]
+mod codec;
+mod crypto;
+mod file_transfer;
+mod membership;
+mod serde_base64;
+/// Messages p2pc nodes send to each other and their encodings. Public with the `bench` feature
+/// only, for the benchmarks.
+#[cfg(feature = "bench")]
+pub mod wire;
+#[cfg(not(feature = "bench"))]
+mod wire;
+
+pub use file_transfer::Attachment;
+pub use membership::{allowed_operations, members, MembershipChange, MembershipOperation};
+
 pub struct ChatMessage {
     pub participants: Vec<String>,
     pub content: String,
     pub id: uuid::Uuid,
     pub chat_id: uuid::Uuid,
     pub answer_to: Option<uuid::Uuid>,
+    /// Time the sender sent the message at, used to order messages that arrive late.
+    pub sent_at: std::time::SystemTime,
+    pub attachment: Option<Attachment>,
+}
+
+/// New content of a message we sent before.
+pub struct MessageEdit {
+    pub participants: Vec<String>,
+    pub message_id: uuid::Uuid,
+    pub chat_id: uuid::Uuid,
+    pub content: String,
+    /// Orders edits of the same message, later edits win.
+    pub edited_at: std::time::SystemTime,
+}
+
+/// Adds or removes an emoji reaction to a message.
+pub struct Reaction {
+    pub participants: Vec<String>,
+    pub chat_id: uuid::Uuid,
+    pub message_id: uuid::Uuid,
+    pub emoji: String,
+    /// `false` if the reaction is taken back.
+    pub added: bool,
+    /// Orders the reactions of a peer with the same emoji to the same message, later ones win.
+    pub reacted_at: std::time::SystemTime,
+}
+
+/// Name, description and icon of a chat, sent to the participants whenever they change.
+pub struct ChatMetadata {
+    pub participants: Vec<String>,
+    pub chat_id: uuid::Uuid,
+    pub name: String,
+    pub description: String,
+    /// Encoded in any common image format. Empty if the chat has no icon.
+    pub icon: Vec<u8>,
+    /// Orders changes of the metadata, later changes win. Changes made at the same time are
+    /// ordered by the peer id of their authors, so all participants pick the same one.
+    pub updated_at: std::time::SystemTime,
+}
+
+/// Whether the user of a node is at the device, announced to the peers of
+/// [`Action::SetPresence`].
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Online,
+    Away,
 }
 
 pub enum Action {
     ListenOn(libp2p::Multiaddr),
     Dial(libp2p::Multiaddr),
+    JoinChat(uuid::Uuid),
+    LeaveChat(uuid::Uuid),
+    /// Adds the given peers to the DHT and bootstraps it. Addresses without a `/p2p` suffix are
+    /// only dialed, their peer ids are learned once connected.
+    Bootstrap(Vec<libp2p::Multiaddr>),
+    /// Looks up the addresses of a peer in the DHT and dials it if it was found.
+    FindPeer(libp2p::PeerId),
     SendMessage(ChatMessage),
+    /// Sends new content for one of our messages to the participants of its chat.
+    EditMessage(MessageEdit),
+    /// Sends a reaction to the participants of its chat.
+    React(Reaction),
+    /// Sends changed metadata to the participants of its chat.
+    UpdateChatMetadata(ChatMetadata),
+    /// Sends membership operations of a chat to `peers`.
+    SendMembershipOperations {
+        chat_id: uuid::Uuid,
+        operations: Vec<MembershipOperation>,
+        peers: Vec<String>,
+    },
+    /// Withdraws one of our messages for all participants of its chat.
+    RetractMessage {
+        message_id: uuid::Uuid,
+        chat_id: uuid::Uuid,
+        participants: Vec<String>,
+    },
+    /// Tells the participants of a chat that we read all messages up to `message_id`.
+    SendReadReceipt {
+        message_id: uuid::Uuid,
+        chat_id: uuid::Uuid,
+        participants: Vec<String>,
+    },
+    /// Tells the participants of a chat that we are typing. At most one signal per chat is sent
+    /// every [`TYPING_SIGNAL_INTERVAL`], more frequent ones are dropped.
+    SendTyping {
+        chat_id: uuid::Uuid,
+        participants: Vec<String>,
+    },
+    /// Announces our presence to `peers` while we are connected to them, right away, whenever
+    /// they connect and every [`PRESENCE_HEARTBEAT_INTERVAL`]. Other peers are not told anything.
+    SetPresence {
+        presence: Presence,
+        peers: Vec<String>,
+    },
+    /// Asks a participant of a chat for the messages it sent after `sent_after`, the send time of
+    /// its newest message we have. Participants only send their own messages again, as messages
+    /// are attributed to the peer they come from.
+    SyncChat {
+        chat_id: uuid::Uuid,
+        peer: libp2p::PeerId,
+        sent_after: Option<std::time::SystemTime>,
+    },
+    /// Sends already sent messages again, only to `peer`. Used to answer
+    /// [`Event::SyncRequested`].
+    SendHistory {
+        peer: libp2p::PeerId,
+        messages: Vec<ChatMessage>,
+    },
+    /// Lets `peers` download the file at `path` as `attachment`. Files are only shared until p2pc
+    /// is restarted.
+    ShareFile {
+        attachment: Attachment,
+        path: std::path::PathBuf,
+        peers: Vec<String>,
+    },
+    /// Downloads an attachment from the peer that shared it to `path`. A download that was
+    /// interrupted before is continued.
+    DownloadFile {
+        peer: libp2p::PeerId,
+        attachment: Attachment,
+        path: std::path::PathBuf,
+    },
 }
 
 pub enum ActionResult {
+    ListenOn {
+        address: libp2p::Multiaddr,
+        result: Result<libp2p::core::transport::ListenerId, libp2p::TransportError<std::io::Error>>,
+    },
+    /// Reported once the connection was established or could not be established. Also reported
+    /// for the addresses dialed by [`Action::Bootstrap`].
+    Dial {
+        address: libp2p::Multiaddr,
+        result: Result<(), libp2p::swarm::DialError>,
+    },
+    /// Reported again if sending the message directly to a peer failed later on and it could not
+    /// be gossiped either.
     SendMessage {
         message_id: uuid::Uuid,
         chat_id: uuid::Uuid,
@@ -27,17 +172,194 @@ pub enum ActionResult {
 
 pub enum Event {
     ActionResult(ActionResult),
-    MessageReceived(ChatMessage),
+    /// `sender` is the peer that sent the message, authenticated along with its encrypted content.
+    /// The participants of the message include the sender and us.
+    MessageReceived {
+        message: ChatMessage,
+        sender: libp2p::PeerId,
+    },
+    /// A participant edited a message. `peer` is the authenticated author of the edit, which has
+    /// to be checked against the sender of the message.
+    MessageEdited {
+        edit: MessageEdit,
+        peer: libp2p::PeerId,
+    },
+    /// `peer` changed the metadata of a chat.
+    ChatMetadataReceived {
+        metadata: ChatMetadata,
+        peer: libp2p::PeerId,
+    },
+    /// `peer` sent membership operations of a chat. Only operations with a valid signature are
+    /// passed on, whether their authors may change the members is up to the receiver.
+    MembershipOperationsReceived {
+        chat_id: uuid::Uuid,
+        operations: Vec<MembershipOperation>,
+        peer: libp2p::PeerId,
+    },
+    /// `peer` reacted to a message.
+    ReactionReceived {
+        reaction: Reaction,
+        peer: libp2p::PeerId,
+    },
+    /// A message was withdrawn. `peer` signed the retraction, which has to be checked against
+    /// the sender of the message.
+    MessageRetracted {
+        message_id: uuid::Uuid,
+        chat_id: uuid::Uuid,
+        peer: libp2p::PeerId,
+    },
+    /// A participant acknowledged that it received one of our messages.
+    MessageDelivered {
+        message_id: uuid::Uuid,
+        chat_id: uuid::Uuid,
+        peer: libp2p::PeerId,
+    },
+    /// A participant read all messages of a chat up to `message_id`.
+    MessageRead {
+        message_id: uuid::Uuid,
+        chat_id: uuid::Uuid,
+        peer: libp2p::PeerId,
+    },
+    /// A participant is typing in a chat. Repeated every [`TYPING_SIGNAL_INTERVAL`] while it keeps
+    /// typing.
+    Typing {
+        chat_id: uuid::Uuid,
+        peer: libp2p::PeerId,
+    },
+    /// A connected peer announced its presence. Repeated every [`PRESENCE_HEARTBEAT_INTERVAL`].
+    Presence {
+        peer: libp2p::PeerId,
+        presence: Presence,
+    },
+    DownloadProgress {
+        file_id: uuid::Uuid,
+        received: u64,
+        size: u64,
+    },
+    /// The file was downloaded completely and its hash matches the attachment.
+    DownloadFinished {
+        file_id: uuid::Uuid,
+        path: std::path::PathBuf,
+    },
+    DownloadFailed {
+        file_id: uuid::Uuid,
+        error: anyhow::Error,
+    },
+    /// A peer asked for the messages of a chat we sent after `sent_after`, or for all of them.
+    SyncRequested {
+        peer: libp2p::PeerId,
+        chat_id: uuid::Uuid,
+        sent_after: Option<std::time::SystemTime>,
+    },
     NewListenAddress(libp2p::Multiaddr),
+    ExpiredListenAddress(libp2p::Multiaddr),
+    /// A listener stopped, e.g. because its interface went down. Its addresses are not listened
+    /// on anymore.
+    ListenerClosed {
+        listener_id: libp2p::core::transport::ListenerId,
+        addresses: Vec<libp2p::Multiaddr>,
+        reason: Result<(), std::io::Error>,
+    },
+    /// The first connection to a peer was established.
+    PeerConnected {
+        peer: libp2p::PeerId,
+        address: libp2p::Multiaddr,
+    },
+    /// The last connection to a peer was closed.
+    PeerDisconnected(libp2p::PeerId),
+    DialFailed {
+        peer: Option<libp2p::PeerId>,
+        error: libp2p::swarm::DialError,
+    },
+    /// A connected peer joined the topic of a chat.
+    Subscribed {
+        peer: libp2p::PeerId,
+        chat_id: uuid::Uuid,
+    },
+    /// A connected peer left the topic of a chat.
+    Unsubscribed {
+        peer: libp2p::PeerId,
+        chat_id: uuid::Uuid,
+    },
+    /// A peer was found on the local network and is being dialed.
+    PeerDiscovered {
+        peer: libp2p::PeerId,
+        address: libp2p::Multiaddr,
+    },
+    /// A peer looked up with [`Action::FindPeer`] was found and is being dialed. Lookups of the
+    /// destinations of messages are not reported.
+    PeerFound {
+        peer: libp2p::PeerId,
+        addresses: Vec<libp2p::Multiaddr>,
+    },
+    PeerNotFound(libp2p::PeerId),
 }
 
 pub struct P2pc {
     sender: tokio::sync::mpsc::UnboundedSender<Action>,
 }
 
+#[derive(Default)]
+pub struct Config {
+    /// Discover and dial peers on the local network via mDNS.
+    pub mdns: bool,
+    /// Relay connections for peers that cannot be reached directly. Only useful on nodes that are
+    /// publicly reachable on their listen addresses.
+    pub relay_server: bool,
+}
+
+/// Minimum time between two typing signals for the same chat.
+pub const TYPING_SIGNAL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Time between two heartbeats to a peer. Shorter than the idle connection timeout, so
+/// connections to the peers we announce our presence to stay open.
+pub const PRESENCE_HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// Minimum time between two lookups of a peer we want to send messages to.
+const PEER_LOOKUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+const IDENTIFY_PROTOCOL_VERSION: &str = "/p2pc/0.1.0";
+const KADEMLIA_PROTOCOL: libp2p::StreamProtocol = libp2p::StreamProtocol::new("/p2pc/kad/1.0.0");
+
+/// Topic shared by all participants of a chat.
+fn chat_topic(chat_id: &uuid::Uuid) -> libp2p::gossipsub::IdentTopic {
+    libp2p::gossipsub::IdentTopic::new(format!("p2pc/chat/{chat_id}"))
+}
+
+/// Extracts the chat id from the hash of a chat topic.
+fn chat_id_of_topic(topic: &libp2p::gossipsub::TopicHash) -> Option<uuid::Uuid> {
+    topic
+        .as_str()
+        .strip_prefix("p2pc/chat/")
+        .and_then(|chat_id| uuid::Uuid::from_str(chat_id).ok())
+}
+
+/// Topic only the given peer subscribes to. Used to reach participants that have not joined the
+/// chat topic yet, e.g. when they are added to a new chat.
+fn peer_topic(peer_id: &str) -> libp2p::gossipsub::IdentTopic {
+    libp2p::gossipsub::IdentTopic::new(format!("p2pc/peer/{peer_id}"))
+}
+
+#[derive(libp2p::swarm::NetworkBehaviour)]
+struct Behaviour {
+    gossipsub: libp2p::gossipsub::Behaviour,
+    request_response: libp2p::request_response::Behaviour<codec::Codec>,
+    file_transfer: libp2p::request_response::json::Behaviour<
+        file_transfer::ChunkRequest,
+        file_transfer::ChunkResponse,
+    >,
+    mdns: libp2p::swarm::behaviour::toggle::Toggle<libp2p::mdns::tokio::Behaviour>,
+    identify: libp2p::identify::Behaviour,
+    kademlia: libp2p::kad::Behaviour<libp2p::kad::store::MemoryStore>,
+    relay_client: libp2p::relay::client::Behaviour,
+    relay: libp2p::swarm::behaviour::toggle::Toggle<libp2p::relay::Behaviour>,
+    dcutr: libp2p::dcutr::Behaviour,
+}
+
 fn build_swarm(
     keypair: libp2p::identity::Keypair,
-) -> anyhow::Result<libp2p::Swarm<libp2p::gossipsub::Behaviour>> {
+    config: &Config,
+) -> anyhow::Result<libp2p::Swarm<Behaviour>> {
     Ok(libp2p::SwarmBuilder::with_existing_identity(keypair)
         .with_tokio()
         .with_tcp(
@@ -45,7 +367,9 @@ fn build_swarm(
             libp2p::noise::Config::new,
             libp2p::yamux::Config::default,
         )?
-        .with_behaviour(|key| {
+        .with_quic()
+        .with_relay_client(libp2p::noise::Config::new, libp2p::yamux::Config::default)?
+        .with_behaviour(|key, relay_client| {
             let gossipsub_message_id_function = |message: &libp2p::gossipsub::Message| {
                 let mut hasher = std::hash::DefaultHasher::new();
                 message.data.hash(&mut hasher);
@@ -67,7 +391,66 @@ fn build_swarm(
                 gossipsub_config,
             )?;
 
-            Ok(gossipsub)
+            let request_response = libp2p::request_response::Behaviour::new(
+                codec::PROTOCOLS
+                    .map(|protocol| (protocol, libp2p::request_response::ProtocolSupport::Full)),
+                libp2p::request_response::Config::default(),
+            );
+
+            let file_transfer = libp2p::request_response::json::Behaviour::new(
+                [(
+                    file_transfer::PROTOCOL,
+                    libp2p::request_response::ProtocolSupport::Full,
+                )],
+                libp2p::request_response::Config::default(),
+            );
+
+            let mdns = if config.mdns {
+                Some(libp2p::mdns::tokio::Behaviour::new(
+                    libp2p::mdns::Config::default(),
+                    key.public().to_peer_id(),
+                )?)
+            } else {
+                None
+            };
+
+            let identify = libp2p::identify::Behaviour::new(libp2p::identify::Config::new(
+                IDENTIFY_PROTOCOL_VERSION.to_string(),
+                key.public(),
+            ));
+
+            let mut kademlia_config = libp2p::kad::Config::default();
+            kademlia_config.set_protocol_names(vec![KADEMLIA_PROTOCOL]);
+            let mut kademlia = libp2p::kad::Behaviour::with_config(
+                key.public().to_peer_id(),
+                libp2p::kad::store::MemoryStore::new(key.public().to_peer_id()),
+                kademlia_config,
+            );
+            // most nodes have no confirmed external address, answer DHT queries anyway
+            kademlia.set_mode(Some(libp2p::kad::Mode::Server));
+
+            let relay = if config.relay_server {
+                Some(libp2p::relay::Behaviour::new(
+                    key.public().to_peer_id(),
+                    libp2p::relay::Config::default(),
+                ))
+            } else {
+                None
+            };
+
+            let dcutr = libp2p::dcutr::Behaviour::new(key.public().to_peer_id());
+
+            Ok(Behaviour {
+                gossipsub,
+                request_response,
+                file_transfer,
+                mdns: mdns.into(),
+                identify,
+                kademlia,
+                relay_client,
+                relay: relay.into(),
+                dcutr,
+            })
         })?
         .with_swarm_config(|cfg| {
             cfg.with_idle_connection_timeout(std::time::Duration::from_secs(30))
@@ -75,114 +458,1190 @@ fn build_swarm(
         .build())
 }
 
-async fn run_event_loop<F>(
-    mut swarm: libp2p::Swarm<libp2p::gossipsub::Behaviour>,
-    mut receiver: tokio::sync::mpsc::UnboundedReceiver<Action>,
-    mut callback: F,
-) where
+/// A message sent directly to a peer, kept until the peer responded so it can be gossiped instead
+/// if the request fails.
+struct PendingDirectMessage {
+    chat_id: uuid::Uuid,
+    /// Id of the chat message, if it is one. Reported as failed if it cannot be gossiped either.
+    message_id: Option<uuid::Uuid>,
+    destination: String,
+    serialized_message: Vec<u8>,
+}
+
+/// Outcome of reading or writing a file for a file transfer. Files are read and written on the
+/// blocking thread pool, so large files do not hold up the event loop.
+enum FileTransferResult {
+    ChunkRead {
+        channel: libp2p::request_response::ResponseChannel<file_transfer::ChunkResponse>,
+        response: file_transfer::ChunkResponse,
+    },
+    ChunkWritten {
+        download: file_transfer::Download,
+        result: anyhow::Result<()>,
+    },
+    DownloadVerified {
+        file_id: uuid::Uuid,
+        result: anyhow::Result<()>,
+    },
+}
+
+struct EventLoop<F> {
+    swarm: libp2p::Swarm<Behaviour>,
+    keypair: libp2p::identity::Keypair,
+    callback: F,
+    pending_direct_messages:
+        HashMap<libp2p::request_response::OutboundRequestId, PendingDirectMessage>,
+    pending_peer_lookups: HashMap<libp2p::kad::QueryId, libp2p::PeerId>,
+    /// Peers looked up by [`Action::FindPeer`], the outcome of their lookups is reported.
+    requested_peer_lookups: HashSet<libp2p::PeerId>,
+    /// When we last looked up the destinations of messages we could not send directly.
+    last_destination_lookups: HashMap<libp2p::PeerId, std::time::Instant>,
+    /// Dials started by actions, reported once they succeeded or failed.
+    pending_dials: HashMap<libp2p::swarm::ConnectionId, libp2p::Multiaddr>,
+    /// Set when a bootstrap was requested before any peer was known.
+    bootstrap_pending: bool,
+    shared_files: HashMap<uuid::Uuid, file_transfer::SharedFile>,
+    downloads: HashMap<uuid::Uuid, file_transfer::Download>,
+    pending_chunk_requests: HashMap<libp2p::request_response::OutboundRequestId, uuid::Uuid>,
+    file_transfer_results: tokio::sync::mpsc::UnboundedSender<FileTransferResult>,
+    /// When we last sent a typing signal for each chat.
+    last_typing_signals: HashMap<uuid::Uuid, std::time::Instant>,
+    presence: Presence,
+    /// Peers we announce our presence to.
+    presence_peers: Vec<libp2p::PeerId>,
+}
+
+impl<F> EventLoop<F>
+where
     F: FnMut(Event) + Send + 'static,
 {
-    let this_node_topic = libp2p::gossipsub::IdentTopic::new("chat");
-    log::info!("subscribing to this node's topic: {}", this_node_topic);
-    swarm.behaviour_mut().subscribe(&this_node_topic).ok();
+    async fn run(
+        mut self,
+        mut receiver: tokio::sync::mpsc::UnboundedReceiver<Action>,
+        mut file_transfer_results: tokio::sync::mpsc::UnboundedReceiver<FileTransferResult>,
+    ) {
+        let this_node_topic = peer_topic(&self.swarm.local_peer_id().to_string());
+        log::info!("subscribing to this node's topic: {}", this_node_topic);
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&this_node_topic)
+            .ok();
 
-    loop {
-        tokio::select! {
-            swarm_event = swarm.select_next_some() => handle_swarm_event(&mut swarm, &swarm_event, &mut callback),
-            Some(action) = receiver.recv() => handle_action(&mut swarm, action, &mut callback),
+        let mut heartbeat_interval = tokio::time::interval(PRESENCE_HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                swarm_event = self.swarm.select_next_some() => self.handle_swarm_event(swarm_event),
+                Some(action) = receiver.recv() => self.handle_action(action),
+                Some(result) = file_transfer_results.recv() => self.handle_file_transfer_result(result),
+                _ = heartbeat_interval.tick() => self.send_heartbeats(),
+            }
         }
     }
-}
 
-fn handle_swarm_event<F>(
-    swarm: &mut libp2p::Swarm<libp2p::gossipsub::Behaviour>,
-    swarm_event: &libp2p::swarm::SwarmEvent<libp2p::gossipsub::Event>,
-    callback: &mut F,
-) where
-    F: FnMut(Event) + Send + 'static,
-{
-    match swarm_event {
-        libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } => {
-            callback(Event::NewListenAddress(address.clone()));
-        }
-        libp2p::swarm::SwarmEvent::Behaviour(libp2p::gossipsub::Event::Message {
-            message, ..
-        }) => {
-            if let Ok(serialized_chat_message) = String::from_utf8(message.data.clone()) {
-                if let Ok(mut chat_message) =
-                    serde_json::from_str::<ChatMessage>(&serialized_chat_message)
+    fn handle_swarm_event(&mut self, swarm_event: libp2p::swarm::SwarmEvent<BehaviourEvent>) {
+        match swarm_event {
+            libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } => {
+                // reservations only succeed if the relay advertises an address to reach it by
+                if self.swarm.behaviour().relay.is_enabled()
+                    && !address
+                        .iter()
+                        .any(|protocol| protocol == libp2p::multiaddr::Protocol::P2pCircuit)
                 {
-                    // remove own id
-                    let local_id = &swarm.local_peer_id().to_string();
-                    if chat_message.participants.contains(local_id) {
-                        chat_message.participants = chat_message
-                            .participants
-                            .into_iter()
-                            .filter(|participant| participant != local_id)
-                            .collect();
+                    self.swarm.add_external_address(address.clone());
+                }
+                (self.callback)(Event::NewListenAddress(address));
+            }
+            libp2p::swarm::SwarmEvent::ExpiredListenAddr { address, .. } => {
+                log::info!("no longer listening on {address}");
+                (self.callback)(Event::ExpiredListenAddress(address));
+            }
+            libp2p::swarm::SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } => {
+                log::info!("listener on {addresses:?} closed: {reason:?}");
+                (self.callback)(Event::ListenerClosed {
+                    listener_id,
+                    addresses,
+                    reason,
+                });
+            }
+            libp2p::swarm::SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                log::info!(
+                    "connected to {peer_id} at {}",
+                    endpoint.get_remote_address()
+                );
+                if let Some(address) = self.pending_dials.remove(&connection_id) {
+                    (self.callback)(Event::ActionResult(ActionResult::Dial {
+                        address,
+                        result: Ok(()),
+                    }));
+                }
+                if num_established.get() == 1 {
+                    (self.callback)(Event::PeerConnected {
+                        peer: peer_id,
+                        address: endpoint.get_remote_address().clone(),
+                    });
+                    if self.presence_peers.contains(&peer_id) {
+                        self.send_heartbeat(&peer_id);
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                cause,
+                ..
+            } => {
+                log::info!("connection to {peer_id} closed: {cause:?}");
+                if num_established == 0 {
+                    (self.callback)(Event::PeerDisconnected(peer_id));
+                }
+            }
+            libp2p::swarm::SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                peer_id,
+                error,
+            } => {
+                log::info!("dialing {peer_id:?} failed: {error}");
+                if let Some(address) = self.pending_dials.remove(&connection_id) {
+                    (self.callback)(Event::ActionResult(ActionResult::Dial {
+                        address,
+                        result: Err(error),
+                    }));
+                } else {
+                    (self.callback)(Event::DialFailed {
+                        peer: peer_id,
+                        error,
+                    });
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(
+                libp2p::gossipsub::Event::Subscribed { peer_id, topic },
+            )) => {
+                if let Some(chat_id) = chat_id_of_topic(&topic) {
+                    (self.callback)(Event::Subscribed {
+                        peer: peer_id,
+                        chat_id,
+                    });
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(
+                libp2p::gossipsub::Event::Unsubscribed { peer_id, topic },
+            )) => {
+                if let Some(chat_id) = chat_id_of_topic(&topic) {
+                    (self.callback)(Event::Unsubscribed {
+                        peer: peer_id,
+                        chat_id,
+                    });
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(
+                libp2p::gossipsub::Event::Message { message, .. },
+            )) => {
+                if let Some(source) = message.source {
+                    match wire::Encoding::detect(&message.data).decode_envelope(&message.data) {
+                        Ok(envelope) => self.handle_envelope(source, envelope),
+                        Err(error) => log::warn!("could not parse message from {source}: {error}"),
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                libp2p::request_response::Event::Message {
+                    peer,
+                    message:
+                        libp2p::request_response::Message::Request {
+                            request, channel, ..
+                        },
+                },
+            )) => {
+                self.handle_envelope(peer, request);
+                self.swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, ())
+                    .ok();
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                libp2p::request_response::Event::Message {
+                    message: libp2p::request_response::Message::Response { request_id, .. },
+                    ..
+                },
+            )) => {
+                self.pending_direct_messages.remove(&request_id);
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                libp2p::request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                if let Some(pending_direct_message) =
+                    self.pending_direct_messages.remove(&request_id)
+                {
+                    log::info!(
+                        "sending message directly to {} failed, falling back to gossip: {error}",
+                        pending_direct_message.destination
+                    );
+                    let optional_errors = publish(
+                        &mut self.swarm,
+                        &pending_direct_message.chat_id,
+                        &[pending_direct_message.destination],
+                        &pending_direct_message.serialized_message,
+                    );
+                    optional_errors
+                        .iter()
+                        .flatten()
+                        .for_each(|error| log::error!("{error}"));
+                    if let (Some(message_id), true) = (
+                        pending_direct_message.message_id,
+                        optional_errors.iter().any(Option::is_some),
+                    ) {
+                        (self.callback)(Event::ActionResult(ActionResult::SendMessage {
+                            message_id,
+                            chat_id: pending_direct_message.chat_id,
+                            optional_errors,
+                        }));
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::FileTransfer(
+                libp2p::request_response::Event::Message {
+                    peer,
+                    message:
+                        libp2p::request_response::Message::Request {
+                            request, channel, ..
+                        },
+                },
+            )) => match self.shared_files.get(&request.file_id) {
+                Some(shared_file) if shared_file.peers.contains(&peer.to_string()) => {
+                    let shared_file = shared_file.clone();
+                    let results = self.file_transfer_results.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let response = match shared_file.read_chunk(request.offset) {
+                            Ok(chunk) => file_transfer::ChunkResponse::Chunk(chunk),
+                            Err(error) => {
+                                log::warn!("could not read {:?}: {error}", shared_file.path);
+                                file_transfer::ChunkResponse::Unavailable
+                            }
+                        };
+                        results
+                            .send(FileTransferResult::ChunkRead { channel, response })
+                            .ok();
+                    });
+                }
+                _ => {
+                    self.swarm
+                        .behaviour_mut()
+                        .file_transfer
+                        .send_response(channel, file_transfer::ChunkResponse::Unavailable)
+                        .ok();
+                }
+            },
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::FileTransfer(
+                libp2p::request_response::Event::Message {
+                    message:
+                        libp2p::request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    ..
+                },
+            )) => {
+                if let Some(file_id) = self.pending_chunk_requests.remove(&request_id) {
+                    match response {
+                        file_transfer::ChunkResponse::Chunk(chunk) => {
+                            if let Some(download) = self.downloads.get(&file_id) {
+                                // the download stays in `downloads` while the chunk is written,
+                                // so it is not started a second time
+                                let mut download = download.clone();
+                                let results = self.file_transfer_results.clone();
+                                tokio::task::spawn_blocking(move || {
+                                    let result = download.write_chunk(&chunk);
+                                    results
+                                        .send(FileTransferResult::ChunkWritten { download, result })
+                                        .ok();
+                                });
+                            }
+                        }
+                        file_transfer::ChunkResponse::Unavailable => {
+                            self.fail_download(file_id, anyhow::anyhow!("file is not available"))
+                        }
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::FileTransfer(
+                libp2p::request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                if let Some(file_id) = self.pending_chunk_requests.remove(&request_id) {
+                    self.fail_download(file_id, error.into());
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Mdns(
+                libp2p::mdns::Event::Discovered(discovered),
+            )) => {
+                for (peer, address) in discovered {
+                    log::info!("discovered {peer} at {address}");
+                    if !self.swarm.is_connected(&peer) {
+                        self.swarm
+                            .dial(
+                                libp2p::swarm::dial_opts::DialOpts::peer_id(peer)
+                                    .addresses(vec![address.clone()])
+                                    .build(),
+                            )
+                            .ok();
+                    }
+                    (self.callback)(Event::PeerDiscovered { peer, address });
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Identify(
+                libp2p::identify::Event::Received { peer_id, info },
+            )) => {
+                if info.protocols.contains(&KADEMLIA_PROTOCOL) {
+                    for address in info.listen_addrs {
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, address);
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
+                libp2p::kad::Event::RoutingUpdated { peer, .. },
+            )) => {
+                log::debug!("routing table updated: {peer}");
+                if self.bootstrap_pending {
+                    self.bootstrap();
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
+                libp2p::kad::Event::OutboundQueryProgressed {
+                    id,
+                    result: libp2p::kad::QueryResult::GetClosestPeers(result),
+                    step,
+                    ..
+                },
+            )) => {
+                if step.last {
+                    if let Some(peer) = self.pending_peer_lookups.remove(&id) {
+                        let closest_peers = match result {
+                            Ok(libp2p::kad::GetClosestPeersOk { peers, .. }) => peers,
+                            Err(libp2p::kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                        };
+                        let addresses = self.known_addresses(&peer);
+                        if closest_peers.contains(&peer) || !addresses.is_empty() {
+                            log::info!("found {peer} at {addresses:?}");
+                            if !self.swarm.is_connected(&peer) {
+                                self.swarm
+                                    .dial(
+                                        libp2p::swarm::dial_opts::DialOpts::peer_id(peer)
+                                            .addresses(addresses.clone())
+                                            .build(),
+                                    )
+                                    .ok();
+                            }
+                            if self.requested_peer_lookups.remove(&peer) {
+                                (self.callback)(Event::PeerFound { peer, addresses });
+                            }
+                        } else {
+                            log::info!("could not find {peer}");
+                            if self.requested_peer_lookups.remove(&peer) {
+                                (self.callback)(Event::PeerNotFound(peer));
+                            }
+                        }
+                    }
+                }
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                libp2p::relay::client::Event::ReservationReqAccepted { relay_peer_id, .. },
+            )) => {
+                log::info!("reserved a slot on relay {relay_peer_id}");
+            }
+            libp2p::swarm::SwarmEvent::Behaviour(BehaviourEvent::Dcutr(libp2p::dcutr::Event {
+                remote_peer_id,
+                result,
+            })) => match result {
+                Ok(_) => log::info!("upgraded relayed connection to {remote_peer_id}"),
+                Err(error) => {
+                    log::info!("could not upgrade connection to {remote_peer_id}: {error}")
+                }
+            },
+            event => log::info!("{event:?}"),
+        }
+    }
 
-                        callback(Event::MessageReceived(chat_message));
+    /// Requests the next chunk of a download or finishes it if it is complete.
+    fn continue_download(&mut self, file_id: uuid::Uuid) {
+        let Some(download) = self.downloads.get(&file_id) else {
+            return;
+        };
+        (self.callback)(Event::DownloadProgress {
+            file_id,
+            received: download.received(),
+            size: download.attachment.size,
+        });
+        if download.is_complete() {
+            let download = download.clone();
+            let results = self.file_transfer_results.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = download.finish();
+                results
+                    .send(FileTransferResult::DownloadVerified { file_id, result })
+                    .ok();
+            });
+        } else {
+            let request_id = self
+                .swarm
+                .behaviour_mut()
+                .file_transfer
+                .send_request(&download.peer, download.next_request());
+            self.pending_chunk_requests.insert(request_id, file_id);
+        }
+    }
+
+    fn handle_file_transfer_result(&mut self, result: FileTransferResult) {
+        match result {
+            FileTransferResult::ChunkRead { channel, response } => {
+                self.swarm
+                    .behaviour_mut()
+                    .file_transfer
+                    .send_response(channel, response)
+                    .ok();
+            }
+            FileTransferResult::ChunkWritten { download, result } => {
+                let file_id = download.attachment.file_id;
+                // the download may have failed in the meantime
+                if let Some(running_download) = self.downloads.get_mut(&file_id) {
+                    match result {
+                        Ok(()) => {
+                            *running_download = download;
+                            self.continue_download(file_id);
+                        }
+                        Err(error) => self.fail_download(file_id, error),
                     }
                 }
             }
+            FileTransferResult::DownloadVerified { file_id, result } => match result {
+                Ok(()) => {
+                    if let Some(download) = self.downloads.remove(&file_id) {
+                        log::info!("downloaded {:?}", download.path);
+                        (self.callback)(Event::DownloadFinished {
+                            file_id,
+                            path: download.path,
+                        });
+                    }
+                }
+                Err(error) => self.fail_download(file_id, error),
+            },
         }
-        event => log::info!("{event:?}"),
     }
-}
 
-fn handle_action<F>(
-    swarm: &mut libp2p::Swarm<libp2p::gossipsub::Behaviour>,
-    action: Action,
-    callback: &mut F,
-) where
-    F: FnMut(Event) + Send + 'static,
-{
-    match action {
-        Action::ListenOn(address) => {
-            swarm.listen_on(address).ok();
-        }
-        Action::Dial(address) => {
-            swarm.dial(address).ok();
-        }
-        Action::SendMessage(mut chat_message) => {
-            let destinations = chat_message.participants.clone();
-            chat_message
-                .participants
-                .push(swarm.local_peer_id().to_string());
-            let optional_errors = match serde_json::to_string(&chat_message) {
-                Ok(serialized_message) => destinations
+    /// Stops a download. Its partial file is kept, so it can be continued later.
+    fn fail_download(&mut self, file_id: uuid::Uuid, error: anyhow::Error) {
+        if self.downloads.remove(&file_id).is_some() {
+            log::warn!("downloading {file_id} failed: {error}");
+            (self.callback)(Event::DownloadFailed { file_id, error });
+        }
+    }
+
+    /// Dials `address` and reports the outcome as [`ActionResult::Dial`].
+    fn dial(&mut self, address: libp2p::Multiaddr) {
+        let dial_opts = libp2p::swarm::dial_opts::DialOpts::from(address.clone());
+        let connection_id = dial_opts.connection_id();
+        match self.swarm.dial(dial_opts) {
+            Ok(()) => {
+                self.pending_dials.insert(connection_id, address);
+            }
+            Err(error) => {
+                log::warn!("could not dial {address}: {error}");
+                (self.callback)(Event::ActionResult(ActionResult::Dial {
+                    address,
+                    result: Err(error),
+                }));
+            }
+        }
+    }
+
+    fn send_heartbeats(&mut self) {
+        for peer in self.presence_peers.clone() {
+            if self.swarm.is_connected(&peer) {
+                self.send_heartbeat(&peer);
+            }
+        }
+    }
+
+    /// Sends our presence directly to `peer`, without falling back to gossip. A missed heartbeat
+    /// is replaced by the next one.
+    fn send_heartbeat(&mut self, peer: &libp2p::PeerId) {
+        let heartbeat = wire::WireMessage::Heartbeat(wire::Heartbeat {
+            presence: self.presence,
+        });
+        self.swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(peer, wire::Envelope::new(heartbeat));
+    }
+
+    fn bootstrap(&mut self) {
+        match self.swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(_) => self.bootstrap_pending = false,
+            Err(error) => {
+                log::info!("postponing bootstrap: {error}");
+                self.bootstrap_pending = true;
+            }
+        }
+    }
+
+    /// Looks `peer` up in the DHT and dials it if it was found, unless a lookup is running already.
+    fn find_peer(&mut self, peer: libp2p::PeerId) {
+        if !self
+            .pending_peer_lookups
+            .values()
+            .any(|pending| pending == &peer)
+        {
+            log::debug!("looking up {peer}");
+            let query_id = self.swarm.behaviour_mut().kademlia.get_closest_peers(peer);
+            self.pending_peer_lookups.insert(query_id, peer);
+        }
+    }
+
+    /// Addresses of `peer` in our DHT routing table.
+    fn known_addresses(&mut self, peer: &libp2p::PeerId) -> Vec<libp2p::Multiaddr> {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .kbucket(*peer)
+            .map(|bucket| {
+                bucket
                     .iter()
-                    .map(|participant| {
-                        log::debug!("participant: {}", participant);
-                        let topic = libp2p::gossipsub::IdentTopic::new("chat");
-                        swarm
-                            .behaviour_mut()
-                            .publish(topic, serialized_message.as_bytes())
-                            .err()
+                    .filter(|entry| entry.node.key.preimage() == peer)
+                    .flat_map(|entry| entry.node.value.iter().cloned().collect::<Vec<_>>())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Handles a message from `source`, which is the authenticated peer that sent or published it.
+    /// Kinds of messages we don't know are skipped, they were added by a newer version of p2pc.
+    fn handle_envelope(&mut self, source: libp2p::PeerId, envelope: wire::Envelope) {
+        if envelope.version > wire::PROTOCOL_VERSION {
+            log::debug!(
+                "{source} uses the newer protocol version {}",
+                envelope.version
+            );
+        }
+        match envelope.content {
+            wire::EnvelopeContent::Known(wire_message) => {
+                self.handle_wire_message(source, wire_message)
+            }
+            wire::EnvelopeContent::Unknown { kind } => log::info!(
+                "ignoring message of kind {kind} from {source} we don't understand, sent with protocol version {}",
+                envelope.version
+            ),
+            wire::EnvelopeContent::Malformed { kind, error } => log::warn!(
+                "could not parse message of kind {kind} from {source}, sent with protocol version {}: {error}",
+                envelope.version
+            ),
+        }
+    }
+
+    fn handle_wire_message(&mut self, source: libp2p::PeerId, wire_message: wire::WireMessage) {
+        match wire_message {
+            wire::WireMessage::ChatMessage(encrypted_chat_message) => {
+                self.handle_chat_message(source, encrypted_chat_message)
+            }
+            wire::WireMessage::Edit(encrypted_edit) => {
+                let local_id = self.swarm.local_peer_id().to_string();
+                if encrypted_edit.participants.contains(&local_id) {
+                    match encrypted_edit.decrypt(&source, &self.keypair) {
+                        Ok(mut edit) => {
+                            edit.participants
+                                .retain(|participant| participant != &local_id);
+                            (self.callback)(Event::MessageEdited { edit, peer: source });
+                        }
+                        Err(error) => log::warn!("could not decrypt edit: {error}"),
+                    }
+                }
+            }
+            wire::WireMessage::Reaction(encrypted_reaction) => {
+                let local_id = self.swarm.local_peer_id().to_string();
+                if encrypted_reaction.participants.contains(&local_id) {
+                    match encrypted_reaction.decrypt(&source, &self.keypair) {
+                        Ok(mut reaction) => {
+                            reaction
+                                .participants
+                                .retain(|participant| participant != &local_id);
+                            (self.callback)(Event::ReactionReceived {
+                                reaction,
+                                peer: source,
+                            });
+                        }
+                        Err(error) => log::warn!("could not decrypt reaction: {error}"),
+                    }
+                }
+            }
+            wire::WireMessage::ChatMetadata(encrypted_metadata) => {
+                let local_id = self.swarm.local_peer_id().to_string();
+                if encrypted_metadata.participants.contains(&local_id) {
+                    match encrypted_metadata.decrypt(&source, &self.keypair) {
+                        Ok(mut metadata) => {
+                            metadata
+                                .participants
+                                .retain(|participant| participant != &local_id);
+                            (self.callback)(Event::ChatMetadataReceived {
+                                metadata,
+                                peer: source,
+                            });
+                        }
+                        Err(error) => log::warn!("could not decrypt chat metadata: {error}"),
+                    }
+                }
+            }
+            wire::WireMessage::Membership(membership) => {
+                let operations = membership
+                    .operations
+                    .into_iter()
+                    .filter(|operation| match operation.verify() {
+                        Ok(()) if operation.chat_id == membership.chat_id => true,
+                        Ok(()) => {
+                            log::warn!(
+                                "ignoring membership operation of another chat from {source}"
+                            );
+                            false
+                        }
+                        Err(error) => {
+                            log::warn!("ignoring membership operation from {source}: {error}");
+                            false
+                        }
                     })
-                    .collect(),
-                Err(_) => vec![],
-            };
-            callback(Event::ActionResult(ActionResult::SendMessage {
-                message_id: chat_message.id,
-                chat_id: chat_message.chat_id,
-                optional_errors,
-            }));
+                    .collect();
+                (self.callback)(Event::MembershipOperationsReceived {
+                    chat_id: membership.chat_id,
+                    operations,
+                    peer: source,
+                });
+            }
+            wire::WireMessage::Retraction(retraction) => match retraction.verify() {
+                Ok(author) => (self.callback)(Event::MessageRetracted {
+                    message_id: retraction.message_id,
+                    chat_id: retraction.chat_id,
+                    peer: author,
+                }),
+                Err(error) => log::warn!("ignoring retraction from {source}: {error}"),
+            },
+            wire::WireMessage::Ack(ack) => {
+                if ack.sender == self.swarm.local_peer_id().to_string() {
+                    (self.callback)(Event::MessageDelivered {
+                        message_id: ack.message_id,
+                        chat_id: ack.chat_id,
+                        peer: source,
+                    });
+                }
+            }
+            wire::WireMessage::ReadReceipt(read_receipt) => {
+                (self.callback)(Event::MessageRead {
+                    message_id: read_receipt.message_id,
+                    chat_id: read_receipt.chat_id,
+                    peer: source,
+                });
+            }
+            wire::WireMessage::Heartbeat(heartbeat) => {
+                (self.callback)(Event::Presence {
+                    peer: source,
+                    presence: heartbeat.presence,
+                });
+            }
+            wire::WireMessage::Typing(typing) => {
+                (self.callback)(Event::Typing {
+                    chat_id: typing.chat_id,
+                    peer: source,
+                });
+            }
+            wire::WireMessage::SyncRequest(sync_request) => {
+                (self.callback)(Event::SyncRequested {
+                    peer: source,
+                    chat_id: sync_request.chat_id,
+                    sent_after: sync_request.sent_after,
+                });
+            }
+        }
+    }
+
+    fn handle_chat_message(
+        &mut self,
+        source: libp2p::PeerId,
+        encrypted_chat_message: wire::EncryptedChatMessage,
+    ) {
+        let local_id = &self.swarm.local_peer_id().to_string();
+        if encrypted_chat_message.participants.contains(local_id) {
+            match encrypted_chat_message.decrypt(&source, &self.keypair) {
+                Ok(chat_message) => {
+                    // messages for new chats arrive on our peer topic, join the chat from now on
+                    join_chat(&mut self.swarm, &chat_message.chat_id);
+
+                    let ack = wire::WireMessage::Ack(wire::Ack {
+                        message_id: chat_message.id,
+                        chat_id: chat_message.chat_id,
+                        sender: source.to_string(),
+                    });
+                    self.send_wire_message(&chat_message.chat_id, &[source.to_string()], ack)
+                        .into_iter()
+                        .flatten()
+                        .for_each(|error| log::warn!("could not send ack: {error}"));
+
+                    (self.callback)(Event::MessageReceived {
+                        message: chat_message,
+                        sender: source,
+                    });
+                }
+                Err(error) => log::warn!("could not decrypt message: {error}"),
+            }
+        }
+    }
+
+    fn handle_action(&mut self, action: Action) {
+        match action {
+            Action::ListenOn(address) => {
+                let result = self.swarm.listen_on(address.clone());
+                if let Err(error) = &result {
+                    log::warn!("could not listen on {address}: {error}");
+                }
+                (self.callback)(Event::ActionResult(ActionResult::ListenOn {
+                    address,
+                    result,
+                }));
+            }
+            Action::Dial(address) => {
+                self.dial(address);
+            }
+            Action::Bootstrap(addresses) => {
+                for address in addresses {
+                    if let Some(libp2p::multiaddr::Protocol::P2p(peer)) = address.iter().last() {
+                        let mut peer_address = address.clone();
+                        peer_address.pop();
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer, peer_address);
+                    }
+                    self.dial(address);
+                }
+                self.bootstrap();
+            }
+            Action::FindPeer(peer) => {
+                self.requested_peer_lookups.insert(peer);
+                self.find_peer(peer);
+            }
+            Action::JoinChat(chat_id) => {
+                join_chat(&mut self.swarm, &chat_id);
+            }
+            Action::LeaveChat(chat_id) => {
+                let topic = chat_topic(&chat_id);
+                log::info!("unsubscribing from chat topic: {}", topic);
+                self.swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .unsubscribe(&topic)
+                    .ok();
+            }
+            Action::SendMessage(mut chat_message) => {
+                join_chat(&mut self.swarm, &chat_message.chat_id);
+
+                let destinations = chat_message.participants.clone();
+                chat_message
+                    .participants
+                    .push(self.swarm.local_peer_id().to_string());
+                let message_id = chat_message.id;
+                let chat_id = chat_message.chat_id;
+                let optional_errors = match wire::EncryptedChatMessage::encrypt(
+                    chat_message,
+                    self.swarm.local_peer_id(),
+                ) {
+                    Ok(encrypted_message) => self.send_wire_message(
+                        &chat_id,
+                        &destinations,
+                        wire::WireMessage::ChatMessage(encrypted_message),
+                    ),
+                    Err(error) => {
+                        log::error!("could not encrypt message: {error}");
+                        vec![]
+                    }
+                };
+                (self.callback)(Event::ActionResult(ActionResult::SendMessage {
+                    message_id,
+                    chat_id,
+                    optional_errors,
+                }));
+            }
+            Action::EditMessage(edit) => {
+                let chat_id = edit.chat_id;
+                let destinations = edit.participants.clone();
+                match wire::EncryptedEdit::encrypt(edit, self.swarm.local_peer_id()) {
+                    Ok(encrypted_edit) => self
+                        .send_wire_message(
+                            &chat_id,
+                            &destinations,
+                            wire::WireMessage::Edit(encrypted_edit),
+                        )
+                        .into_iter()
+                        .flatten()
+                        .for_each(|error| log::warn!("could not send edit: {error}")),
+                    Err(error) => log::error!("could not encrypt edit: {error}"),
+                }
+            }
+            Action::React(reaction) => {
+                let chat_id = reaction.chat_id;
+                let destinations = reaction.participants.clone();
+                match wire::EncryptedReaction::encrypt(reaction, self.swarm.local_peer_id()) {
+                    Ok(encrypted_reaction) => self
+                        .send_wire_message(
+                            &chat_id,
+                            &destinations,
+                            wire::WireMessage::Reaction(encrypted_reaction),
+                        )
+                        .into_iter()
+                        .flatten()
+                        .for_each(|error| log::warn!("could not send reaction: {error}")),
+                    Err(error) => log::error!("could not encrypt reaction: {error}"),
+                }
+            }
+            Action::UpdateChatMetadata(metadata) => {
+                let chat_id = metadata.chat_id;
+                let destinations = metadata.participants.clone();
+                match wire::EncryptedChatMetadata::encrypt(metadata, self.swarm.local_peer_id()) {
+                    Ok(encrypted_metadata) => self
+                        .send_wire_message(
+                            &chat_id,
+                            &destinations,
+                            wire::WireMessage::ChatMetadata(encrypted_metadata),
+                        )
+                        .into_iter()
+                        .flatten()
+                        .for_each(|error| log::warn!("could not send chat metadata: {error}")),
+                    Err(error) => log::error!("could not encrypt chat metadata: {error}"),
+                }
+            }
+            Action::SendMembershipOperations {
+                chat_id,
+                operations,
+                peers,
+            } => {
+                let membership = wire::WireMessage::Membership(wire::Membership {
+                    chat_id,
+                    operations,
+                });
+                self.send_wire_message(&chat_id, &peers, membership)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|error| log::warn!("could not send membership operations: {error}"));
+            }
+            Action::RetractMessage {
+                message_id,
+                chat_id,
+                participants,
+            } => match wire::Retraction::sign(chat_id, message_id, &self.keypair) {
+                Ok(retraction) => self
+                    .send_wire_message(
+                        &chat_id,
+                        &participants,
+                        wire::WireMessage::Retraction(retraction),
+                    )
+                    .into_iter()
+                    .flatten()
+                    .for_each(|error| log::warn!("could not send retraction: {error}")),
+                Err(error) => log::error!("could not sign retraction: {error}"),
+            },
+            Action::SendReadReceipt {
+                message_id,
+                chat_id,
+                participants,
+            } => {
+                let read_receipt = wire::WireMessage::ReadReceipt(wire::ReadReceipt {
+                    message_id,
+                    chat_id,
+                });
+                self.send_wire_message(&chat_id, &participants, read_receipt)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|error| log::warn!("could not send read receipt: {error}"));
+            }
+            Action::SendTyping {
+                chat_id,
+                participants,
+            } => {
+                let now = std::time::Instant::now();
+                if self
+                    .last_typing_signals
+                    .get(&chat_id)
+                    .is_some_and(|last_signal| now < *last_signal + TYPING_SIGNAL_INTERVAL)
+                {
+                    return;
+                }
+                self.last_typing_signals.insert(chat_id, now);
+                let typing = wire::WireMessage::Typing(wire::Typing {
+                    chat_id,
+                    sent_at: std::time::SystemTime::now(),
+                });
+                self.send_wire_message(&chat_id, &participants, typing)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|error| log::debug!("could not send typing signal: {error}"));
+            }
+            Action::SetPresence { presence, peers } => {
+                self.presence = presence;
+                self.presence_peers = peers
+                    .iter()
+                    .filter_map(|peer| libp2p::PeerId::from_str(peer).ok())
+                    .collect();
+                self.send_heartbeats();
+            }
+            Action::SyncChat {
+                chat_id,
+                peer,
+                sent_after,
+            } => {
+                let sync_request = wire::WireMessage::SyncRequest(wire::SyncRequest {
+                    chat_id,
+                    sent_after,
+                });
+                self.send_wire_message(&chat_id, &[peer.to_string()], sync_request)
+                    .into_iter()
+                    .flatten()
+                    .for_each(|error| log::warn!("could not send sync request: {error}"));
+            }
+            Action::ShareFile {
+                attachment,
+                path,
+                peers,
+            } => {
+                self.shared_files.insert(
+                    attachment.file_id,
+                    file_transfer::SharedFile { path, peers },
+                );
+            }
+            Action::DownloadFile {
+                peer,
+                attachment,
+                path,
+            } => {
+                let file_id = attachment.file_id;
+                if self.downloads.contains_key(&file_id) {
+                    return;
+                }
+                match file_transfer::Download::new(peer, attachment, path) {
+                    Ok(download) => {
+                        self.downloads.insert(file_id, download);
+                        self.continue_download(file_id);
+                    }
+                    Err(error) => {
+                        (self.callback)(Event::DownloadFailed {
+                            file_id,
+                            error: error.into(),
+                        });
+                    }
+                }
+            }
+            Action::SendHistory { peer, messages } => {
+                for mut chat_message in messages {
+                    chat_message
+                        .participants
+                        .push(self.swarm.local_peer_id().to_string());
+                    let chat_id = chat_message.chat_id;
+                    match wire::EncryptedChatMessage::encrypt(
+                        chat_message,
+                        self.swarm.local_peer_id(),
+                    ) {
+                        Ok(encrypted_message) => self
+                            .send_wire_message(
+                                &chat_id,
+                                &[peer.to_string()],
+                                wire::WireMessage::ChatMessage(encrypted_message),
+                            )
+                            .into_iter()
+                            .flatten()
+                            .for_each(|error| log::warn!("could not send history: {error}")),
+                        Err(error) => log::error!("could not encrypt message: {error}"),
+                    }
+                }
+            }
         }
     }
+
+    /// Sends the message directly to every destination we are connected to and gossips it to the
+    /// remaining ones.
+    fn send_wire_message(
+        &mut self,
+        chat_id: &uuid::Uuid,
+        destinations: &[String],
+        wire_message: wire::WireMessage,
+    ) -> Vec<Option<libp2p::gossipsub::PublishError>> {
+        let message_id = match &wire_message {
+            wire::WireMessage::ChatMessage(chat_message) => Some(chat_message.id),
+            _ => None,
+        };
+        let envelope = wire::Envelope::new(wire_message);
+        let serialized_message = match wire::Encoding::Json.encode(&envelope) {
+            Ok(serialized_message) => serialized_message,
+            Err(error) => {
+                log::error!("could not serialize message: {error}");
+                return vec![];
+            }
+        };
+
+        let mut optional_errors = vec![];
+        let mut gossip_destinations = vec![];
+        for destination in destinations {
+            match libp2p::PeerId::from_str(destination) {
+                Ok(peer_id) if self.swarm.is_connected(&peer_id) => {
+                    log::debug!("sending message directly to {}", destination);
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_request(&peer_id, envelope.clone());
+                    self.pending_direct_messages.insert(
+                        request_id,
+                        PendingDirectMessage {
+                            chat_id: *chat_id,
+                            message_id,
+                            destination: destination.clone(),
+                            serialized_message: serialized_message.clone(),
+                        },
+                    );
+                    optional_errors.push(None);
+                }
+                Ok(peer_id) => {
+                    // look the destination up so the next messages can be sent directly
+                    let now = std::time::Instant::now();
+                    if self
+                        .last_destination_lookups
+                        .get(&peer_id)
+                        .map_or(true, |last_lookup| {
+                            now >= *last_lookup + PEER_LOOKUP_INTERVAL
+                        })
+                    {
+                        self.last_destination_lookups.insert(peer_id, now);
+                        self.find_peer(peer_id);
+                    }
+                    gossip_destinations.push(destination.clone());
+                }
+                Err(_) => gossip_destinations.push(destination.clone()),
+            }
+        }
+
+        if !gossip_destinations.is_empty() {
+            optional_errors.extend(publish(
+                &mut self.swarm,
+                chat_id,
+                &gossip_destinations,
+                &serialized_message,
+            ));
+        }
+        optional_errors
+    }
+}
+
+fn join_chat(swarm: &mut libp2p::Swarm<Behaviour>, chat_id: &uuid::Uuid) {
+    let topic = chat_topic(chat_id);
+    if let Ok(true) = swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+        log::info!("subscribed to chat topic: {}", topic);
+    }
+}
+
+/// Publishes `data` on the chat topic. Destinations that are connected to us but are not
+/// subscribed to the chat topic yet get the message on their own peer topic instead.
+fn publish(
+    swarm: &mut libp2p::Swarm<Behaviour>,
+    chat_id: &uuid::Uuid,
+    destinations: &[String],
+    data: &[u8],
+) -> Vec<Option<libp2p::gossipsub::PublishError>> {
+    let chat_topic = chat_topic(chat_id);
+    let chat_topic_hash = chat_topic.hash();
+
+    let mut chat_topic_has_subscribers = false;
+    let mut peer_topics = vec![];
+    for (peer_id, topics) in swarm.behaviour().gossipsub.all_peers() {
+        let peer_id = peer_id.to_string();
+        if topics.contains(&&chat_topic_hash) {
+            chat_topic_has_subscribers = true;
+        } else if destinations.contains(&peer_id) {
+            let peer_topic = peer_topic(&peer_id);
+            if topics.contains(&&peer_topic.hash()) {
+                peer_topics.push(peer_topic);
+            }
+        }
+    }
+
+    let mut optional_errors = vec![];
+    // publish to the chat topic even without known subscribers so the caller gets an error
+    if chat_topic_has_subscribers || peer_topics.is_empty() {
+        log::debug!("publishing to chat topic: {}", chat_topic);
+        optional_errors.push(
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(chat_topic, data)
+                .err(),
+        );
+    }
+    for peer_topic in peer_topics {
+        log::debug!("publishing to peer topic: {}", peer_topic);
+        optional_errors.push(
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(peer_topic, data)
+                .err(),
+        );
+    }
+    optional_errors
 }
 
 impl P2pc {
-    pub fn new<F>(keypair: libp2p::identity::Keypair, callback: F) -> anyhow::Result<Self>
+    pub fn new<F>(
+        keypair: libp2p::identity::Keypair,
+        config: Config,
+        callback: F,
+    ) -> anyhow::Result<Self>
     where
         F: FnMut(Event) + Send + 'static,
     {
-        let swarm = build_swarm(keypair)?;
+        let (file_transfer_results, file_transfer_result_receiver) =
+            tokio::sync::mpsc::unbounded_channel();
+        let event_loop = EventLoop {
+            swarm: build_swarm(keypair.clone(), &config)?,
+            keypair,
+            callback,
+            pending_direct_messages: HashMap::new(),
+            pending_peer_lookups: HashMap::new(),
+            requested_peer_lookups: HashSet::new(),
+            last_destination_lookups: HashMap::new(),
+            pending_dials: HashMap::new(),
+            bootstrap_pending: false,
+            shared_files: HashMap::new(),
+            downloads: HashMap::new(),
+            pending_chunk_requests: HashMap::new(),
+            file_transfer_results,
+            last_typing_signals: HashMap::new(),
+            presence: Presence::Online,
+            presence_peers: vec![],
+        };
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
-        tokio::spawn(run_event_loop(swarm, receiver, callback));
+        tokio::spawn(event_loop.run(receiver, file_transfer_result_receiver));
         Ok(Self { sender })
     }
 
+    #[allow(clippy::result_large_err)] // the error hands the action back to the caller
     pub fn execute(
         &mut self,
         action: Action,
@@ -0,0 +1,251 @@
+// Changes of the members of a chat.
+//
+// Every change is an operation signed by its author. Members keep all operations with a valid
+// signature and replay them in the order of their clocks to get the members of the chat. Whether
+// the author of an operation may change the members is decided during the replay, so all members
+// end up with the same participants once they know the same operations, no matter in which order
+// they got them.
+
+use crate::crypto;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MembershipChange {
+    Add,
+    /// Removes a member, or leaves the chat if the author removes itself.
+    Remove,
+}
+
+/// Signed change of the members of a chat.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MembershipOperation {
+    pub chat_id: uuid::Uuid,
+    pub member: String,
+    pub change: MembershipChange,
+    /// Lamport timestamp. Operations are applied in the order of their clocks, ties are broken by
+    /// the author.
+    pub clock: u64,
+    pub author: String,
+    #[serde(with = "crate::serde_base64")]
+    signature: Vec<u8>,
+}
+
+impl MembershipOperation {
+    pub fn sign(
+        chat_id: uuid::Uuid,
+        member: String,
+        change: MembershipChange,
+        clock: u64,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<Self> {
+        let author = keypair.public().to_peer_id().to_string();
+        let signature = crypto::sign(
+            &signed_payload(&chat_id, &member, change, clock, &author)?,
+            keypair,
+        )?;
+        Ok(Self {
+            chat_id,
+            member,
+            change,
+            clock,
+            author,
+            signature,
+        })
+    }
+
+    pub fn verify(&self) -> anyhow::Result<()> {
+        crypto::verify(
+            &signed_payload(
+                &self.chat_id,
+                &self.member,
+                self.change,
+                self.clock,
+                &self.author,
+            )?,
+            &self.signature,
+            &self.author,
+        )
+    }
+
+    /// Whether the author may make this change, given the members before it. Everyone may leave.
+    fn is_allowed(&self, members: &[String]) -> bool {
+        let is_leave = self.change == MembershipChange::Remove && self.author == self.member;
+        is_leave || members.contains(&self.author)
+    }
+
+    fn order(&self) -> (u64, &str, &str, bool) {
+        (
+            self.clock,
+            &self.author,
+            &self.member,
+            self.change == MembershipChange::Remove,
+        )
+    }
+}
+
+/// Members of a chat that started with `founders` after applying `operations` in their order.
+/// Operations of authors that are not a member at that point are skipped.
+pub fn members(founders: Vec<String>, operations: &[MembershipOperation]) -> Vec<String> {
+    replay(founders, operations).0
+}
+
+/// The `operations` that are not skipped when replaying them for a chat that started with
+/// `founders`, in the order they were given in.
+pub fn allowed_operations(
+    founders: Vec<String>,
+    operations: Vec<MembershipOperation>,
+) -> Vec<MembershipOperation> {
+    let (_, allowed) = replay(founders, &operations);
+    operations
+        .into_iter()
+        .zip(allowed)
+        .filter_map(|(operation, allowed)| allowed.then_some(operation))
+        .collect()
+}
+
+/// Applies the operations in their order. Returns the members and which operations were allowed.
+fn replay(founders: Vec<String>, operations: &[MembershipOperation]) -> (Vec<String>, Vec<bool>) {
+    let mut order = (0..operations.len()).collect::<Vec<_>>();
+    order.sort_by(|&index, &other| operations[index].order().cmp(&operations[other].order()));
+    let mut allowed = vec![false; operations.len()];
+    let mut members = founders;
+    for index in order {
+        let operation = &operations[index];
+        if !operation.is_allowed(&members) {
+            continue;
+        }
+        allowed[index] = true;
+        match operation.change {
+            MembershipChange::Add => {
+                if !members.contains(&operation.member) {
+                    members.push(operation.member.clone());
+                }
+            }
+            MembershipChange::Remove => members.retain(|member| *member != operation.member),
+        }
+    }
+    (members, allowed)
+}
+
+fn signed_payload(
+    chat_id: &uuid::Uuid,
+    member: &str,
+    change: MembershipChange,
+    clock: u64,
+    author: &str,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&(
+        "p2pc membership",
+        chat_id,
+        member,
+        change,
+        clock,
+        author,
+    ))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Peer {
+        keypair: libp2p::identity::Keypair,
+        id: String,
+    }
+
+    /// Peers ordered by their ids, which break ties between operations with the same clock.
+    fn peers<const N: usize>() -> [Peer; N] {
+        let mut peers: [Peer; N] = std::array::from_fn(|_| {
+            let keypair = libp2p::identity::Keypair::generate_ed25519();
+            let id = keypair.public().to_peer_id().to_string();
+            Peer { keypair, id }
+        });
+        peers.sort_by(|peer, other| peer.id.cmp(&other.id));
+        peers
+    }
+
+    fn operation(
+        chat_id: uuid::Uuid,
+        author: &Peer,
+        member: &Peer,
+        change: MembershipChange,
+        clock: u64,
+    ) -> MembershipOperation {
+        MembershipOperation::sign(chat_id, member.id.clone(), change, clock, &author.keypair)
+            .unwrap()
+    }
+
+    fn sorted(mut members: Vec<String>) -> Vec<String> {
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn operations_are_verified() {
+        let [alice, bob] = peers();
+        let mut operation = operation(uuid::Uuid::new_v4(), &alice, &bob, MembershipChange::Add, 1);
+        assert!(operation.verify().is_ok());
+        operation.change = MembershipChange::Remove;
+        assert!(operation.verify().is_err());
+    }
+
+    #[test]
+    fn members_do_not_depend_on_the_order_of_operations() {
+        let [alice, bob, carol, dave] = peers();
+        let chat_id = uuid::Uuid::new_v4();
+        let founders = vec![alice.id.clone(), bob.id.clone(), carol.id.clone()];
+        // alice removes bob while bob adds dave, the removal wins as alice has the lower id
+        let operations = [
+            operation(chat_id, &alice, &bob, MembershipChange::Remove, 2),
+            operation(chat_id, &bob, &dave, MembershipChange::Add, 2),
+            operation(chat_id, &carol, &alice, MembershipChange::Add, 1),
+        ];
+        let expected = sorted(vec![alice.id.clone(), carol.id.clone()]);
+        for order in [[0, 1, 2], [1, 0, 2], [2, 1, 0], [1, 2, 0]] {
+            let operations = order.map(|index| operations[index].clone());
+            assert_eq!(sorted(members(founders.clone(), &operations)), expected);
+        }
+    }
+
+    #[test]
+    fn operations_of_non_members_are_skipped() {
+        let [alice, bob, mallory] = peers();
+        let chat_id = uuid::Uuid::new_v4();
+        let operations = [
+            operation(chat_id, &mallory, &mallory, MembershipChange::Add, 1),
+            operation(chat_id, &mallory, &bob, MembershipChange::Remove, 2),
+        ];
+        assert_eq!(
+            members(vec![alice.id.clone(), bob.id.clone()], &operations),
+            vec![alice.id.clone(), bob.id.clone()]
+        );
+    }
+
+    #[test]
+    fn only_allowed_operations_are_kept() {
+        let [alice, bob, mallory] = peers();
+        let chat_id = uuid::Uuid::new_v4();
+        let operations = vec![
+            operation(chat_id, &mallory, &mallory, MembershipChange::Add, 1),
+            operation(chat_id, &bob, &alice, MembershipChange::Remove, 3),
+            operation(chat_id, &alice, &mallory, MembershipChange::Add, 2),
+        ];
+        // mallory may only change the members once alice added it
+        assert_eq!(
+            allowed_operations(vec![alice.id.clone(), bob.id.clone()], operations.clone()),
+            operations[1..]
+        );
+    }
+
+    #[test]
+    fn everyone_may_leave() {
+        let [alice, bob] = peers();
+        let chat_id = uuid::Uuid::new_v4();
+        let operations = [
+            operation(chat_id, &alice, &bob, MembershipChange::Remove, 1),
+            // bob was removed already, but may still leave
+            operation(chat_id, &bob, &bob, MembershipChange::Remove, 2),
+            operation(chat_id, &alice, &alice, MembershipChange::Remove, 3),
+        ];
+        assert!(members(vec![alice.id.clone(), bob.id.clone()], &operations).is_empty());
+    }
+}
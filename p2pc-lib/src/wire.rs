@@ -0,0 +1,699 @@
+use crate::{crypto, ChatMessage, ChatMetadata, MessageEdit, Reaction};
+
+/// Version of the wire protocol. Raised whenever a kind of message changes in a way older nodes
+/// cannot understand. New kinds don't need a new version, nodes skip the kinds they don't know.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Formats messages are encoded in. Gossiped messages are JSON, as we cannot tell which formats
+/// their receivers understand. Messages sent directly use the format negotiated with the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    /// Tells the encoding of a message apart by its first byte, JSON messages are objects.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes.first() {
+            Some(b'{') => Self::Json,
+            _ => Self::Cbor,
+        }
+    }
+
+    pub fn encode<T: serde::Serialize>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(value)?),
+            Self::Cbor => {
+                let mut bytes = vec![];
+                ciborium::into_writer(value, &mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode<T: serde::de::DeserializeOwned>(self, bytes: &[u8]) -> anyhow::Result<T> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(bytes)?),
+            Self::Cbor => Ok(ciborium::from_reader(bytes)?),
+        }
+    }
+
+    /// Decodes the version and kind of an envelope before its payload, so kinds we don't know are
+    /// told apart from known kinds with a payload we cannot parse.
+    pub fn decode_envelope(self, bytes: &[u8]) -> anyhow::Result<Envelope> {
+        let Header { version, kind } = self.decode(bytes)?;
+        let content = if WireMessage::is_known_kind(&kind) {
+            match self.decode(bytes) {
+                Ok(wire_message) => EnvelopeContent::Known(wire_message),
+                Err(error) => EnvelopeContent::Malformed {
+                    kind,
+                    error: error.to_string(),
+                },
+            }
+        } else {
+            EnvelopeContent::Unknown { kind }
+        };
+        Ok(Envelope { version, content })
+    }
+}
+
+/// Everything p2pc nodes send to each other, either gossiped or directly. Serialized as
+/// `{"version": 2, "kind": "ChatMessage", "payload": {...}}`, decoded with
+/// [`Encoding::decode_envelope`].
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Envelope {
+    pub version: u32,
+    #[serde(flatten)]
+    pub content: EnvelopeContent,
+}
+
+impl Envelope {
+    pub fn new(wire_message: WireMessage) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            content: EnvelopeContent::Known(wire_message),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum EnvelopeContent {
+    Known(WireMessage),
+    /// A kind of message added by a newer version of p2pc.
+    Unknown {
+        kind: String,
+    },
+    /// A kind of message we know, with a payload we could not parse.
+    Malformed {
+        kind: String,
+        error: String,
+    },
+}
+
+/// The fields every envelope has, no matter its kind.
+#[derive(serde::Deserialize)]
+struct Header {
+    version: u32,
+    kind: String,
+}
+
+/// The kinds of messages p2pc nodes send to each other.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(tag = "kind", content = "payload")]
+pub enum WireMessage {
+    ChatMessage(EncryptedChatMessage),
+    Edit(EncryptedEdit),
+    Retraction(Retraction),
+    Reaction(EncryptedReaction),
+    ChatMetadata(EncryptedChatMetadata),
+    Membership(Membership),
+    Ack(Ack),
+    ReadReceipt(ReadReceipt),
+    SyncRequest(SyncRequest),
+    Typing(Typing),
+    Heartbeat(Heartbeat),
+}
+
+impl WireMessage {
+    /// Names of the kinds above, as they appear in the `kind` field of an envelope.
+    const KINDS: &'static [&'static str] = &[
+        "ChatMessage",
+        "Edit",
+        "Retraction",
+        "Reaction",
+        "ChatMetadata",
+        "Membership",
+        "Ack",
+        "ReadReceipt",
+        "SyncRequest",
+        "Typing",
+        "Heartbeat",
+    ];
+
+    /// Whether `kind` is one of the kinds above.
+    fn is_known_kind(kind: &str) -> bool {
+        Self::KINDS.contains(&kind)
+    }
+}
+
+/// Wire representation of a [`ChatMessage`]. Only the information needed for routing is sent in
+/// plaintext, the rest is encrypted for the participants. The plaintext fields are authenticated
+/// along with the encrypted ones, so they cannot be changed on the way. The encrypted content is
+/// always CBOR, it is the same for all recipients no matter which encoding they understand.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct EncryptedChatMessage {
+    /// Peer id of the sender, messages forwarded by anyone else are rejected.
+    pub sender: String,
+    pub participants: Vec<String>,
+    pub id: uuid::Uuid,
+    pub chat_id: uuid::Uuid,
+    sealed_content: crypto::Sealed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChatMessageContent {
+    content: String,
+    answer_to: Option<uuid::Uuid>,
+    #[serde(default = "std::time::SystemTime::now")]
+    sent_at: std::time::SystemTime,
+    #[serde(default)]
+    attachment: Option<crate::Attachment>,
+}
+
+/// Wire representation of a [`MessageEdit`], encrypted like a chat message.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct EncryptedEdit {
+    pub sender: String,
+    pub participants: Vec<String>,
+    pub message_id: uuid::Uuid,
+    pub chat_id: uuid::Uuid,
+    sealed_content: crypto::Sealed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EditContent {
+    content: String,
+    edited_at: std::time::SystemTime,
+}
+
+/// Wire representation of a [`Reaction`], encrypted like a chat message.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct EncryptedReaction {
+    pub sender: String,
+    pub participants: Vec<String>,
+    pub chat_id: uuid::Uuid,
+    sealed_content: crypto::Sealed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ReactionContent {
+    message_id: uuid::Uuid,
+    emoji: String,
+    added: bool,
+    reacted_at: std::time::SystemTime,
+}
+
+/// Wire representation of [`ChatMetadata`], encrypted like a chat message.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct EncryptedChatMetadata {
+    pub sender: String,
+    pub participants: Vec<String>,
+    pub chat_id: uuid::Uuid,
+    sealed_content: crypto::Sealed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChatMetadataContent {
+    name: String,
+    description: String,
+    #[serde(with = "crate::serde_base64")]
+    icon: Vec<u8>,
+    updated_at: std::time::SystemTime,
+}
+
+/// Membership operations of a chat. The operations are signed, so they can be forwarded by anyone.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Membership {
+    pub chat_id: uuid::Uuid,
+    pub operations: Vec<crate::MembershipOperation>,
+}
+
+/// Withdraws a message for everyone. Signed by the sender of the message, so it can be verified
+/// no matter who forwarded it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Retraction {
+    pub chat_id: uuid::Uuid,
+    pub message_id: uuid::Uuid,
+    pub author: String,
+    #[serde(with = "crate::serde_base64")]
+    signature: Vec<u8>,
+}
+
+/// Sent by a recipient of a chat message to its sender once the message was received.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Ack {
+    pub message_id: uuid::Uuid,
+    pub chat_id: uuid::Uuid,
+    /// Peer id of the sender of the acknowledged message. Acks can be gossiped on the chat topic,
+    /// so everyone else has to ignore them.
+    pub sender: String,
+}
+
+/// Tells the other participants of a chat that we read all messages up to `message_id`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ReadReceipt {
+    pub message_id: uuid::Uuid,
+    pub chat_id: uuid::Uuid,
+}
+
+/// Asks a participant of a chat for the messages it sent that we do not know yet. Only the send
+/// time of the newest message of the participant we have is sent, so the request stays small no
+/// matter how long the chat is. The time comes from the clock of the participant itself.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SyncRequest {
+    pub chat_id: uuid::Uuid,
+    pub sent_after: Option<std::time::SystemTime>,
+}
+
+/// Tells the other participants of a chat that we are typing.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Typing {
+    pub chat_id: uuid::Uuid,
+    /// Makes every signal unique, gossipsub drops messages with a content it has seen before.
+    pub sent_at: std::time::SystemTime,
+}
+
+/// Sent directly to connected peers that may know whether we are online.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Heartbeat {
+    pub presence: crate::Presence,
+}
+
+impl EncryptedChatMessage {
+    pub fn encrypt(chat_message: ChatMessage, sender: &libp2p::PeerId) -> anyhow::Result<Self> {
+        let content = Encoding::Cbor.encode(&ChatMessageContent {
+            content: chat_message.content,
+            answer_to: chat_message.answer_to,
+            sent_at: chat_message.sent_at,
+            attachment: chat_message.attachment,
+        })?;
+        let sender = sender.to_string();
+        let associated_data = Self::associated_data(
+            &sender,
+            &chat_message.participants,
+            &chat_message.id,
+            &chat_message.chat_id,
+        )?;
+        Ok(Self {
+            sealed_content: crypto::seal(&content, &associated_data, &chat_message.participants)?,
+            sender,
+            participants: chat_message.participants,
+            id: chat_message.id,
+            chat_id: chat_message.chat_id,
+        })
+    }
+
+    /// Fails if the message was not sent by `source`, the peer it was received from.
+    pub fn decrypt(
+        self,
+        source: &libp2p::PeerId,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<ChatMessage> {
+        check_sender(&self.sender, source)?;
+        let associated_data =
+            Self::associated_data(&self.sender, &self.participants, &self.id, &self.chat_id)?;
+        let content: ChatMessageContent = Encoding::Cbor.decode(&crypto::open(
+            &self.sealed_content,
+            &associated_data,
+            keypair,
+        )?)?;
+        Ok(ChatMessage {
+            participants: self.participants,
+            content: content.content,
+            id: self.id,
+            chat_id: self.chat_id,
+            answer_to: content.answer_to,
+            sent_at: content.sent_at,
+            attachment: content.attachment,
+        })
+    }
+
+    fn associated_data(
+        sender: &str,
+        participants: &[String],
+        id: &uuid::Uuid,
+        chat_id: &uuid::Uuid,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(
+            "p2pc chat message",
+            sender,
+            participants,
+            id,
+            chat_id,
+        ))?)
+    }
+}
+
+impl EncryptedEdit {
+    pub fn encrypt(edit: MessageEdit, sender: &libp2p::PeerId) -> anyhow::Result<Self> {
+        let content = Encoding::Cbor.encode(&EditContent {
+            content: edit.content,
+            edited_at: edit.edited_at,
+        })?;
+        let sender = sender.to_string();
+        let associated_data =
+            Self::associated_data(&sender, &edit.participants, &edit.message_id, &edit.chat_id)?;
+        Ok(Self {
+            sealed_content: crypto::seal(&content, &associated_data, &edit.participants)?,
+            sender,
+            participants: edit.participants,
+            message_id: edit.message_id,
+            chat_id: edit.chat_id,
+        })
+    }
+
+    /// Fails if the message was not sent by `source`, the peer it was received from.
+    pub fn decrypt(
+        self,
+        source: &libp2p::PeerId,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<MessageEdit> {
+        check_sender(&self.sender, source)?;
+        let associated_data = Self::associated_data(
+            &self.sender,
+            &self.participants,
+            &self.message_id,
+            &self.chat_id,
+        )?;
+        let content: EditContent = Encoding::Cbor.decode(&crypto::open(
+            &self.sealed_content,
+            &associated_data,
+            keypair,
+        )?)?;
+        Ok(MessageEdit {
+            participants: self.participants,
+            message_id: self.message_id,
+            chat_id: self.chat_id,
+            content: content.content,
+            edited_at: content.edited_at,
+        })
+    }
+
+    fn associated_data(
+        sender: &str,
+        participants: &[String],
+        message_id: &uuid::Uuid,
+        chat_id: &uuid::Uuid,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(
+            "p2pc edit",
+            sender,
+            participants,
+            message_id,
+            chat_id,
+        ))?)
+    }
+}
+
+impl Retraction {
+    pub fn sign(
+        chat_id: uuid::Uuid,
+        message_id: uuid::Uuid,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            chat_id,
+            message_id,
+            author: keypair.public().to_peer_id().to_string(),
+            signature: crypto::sign(&Self::signed_payload(&chat_id, &message_id), keypair)?,
+        })
+    }
+
+    /// Checks the signature and returns the author of the retraction.
+    pub fn verify(&self) -> anyhow::Result<libp2p::PeerId> {
+        crypto::verify(
+            &Self::signed_payload(&self.chat_id, &self.message_id),
+            &self.signature,
+            &self.author,
+        )?;
+        Ok(self.author.parse()?)
+    }
+
+    fn signed_payload(chat_id: &uuid::Uuid, message_id: &uuid::Uuid) -> Vec<u8> {
+        [
+            b"p2pc retraction".as_slice(),
+            chat_id.as_bytes(),
+            message_id.as_bytes(),
+        ]
+        .concat()
+    }
+}
+
+impl EncryptedReaction {
+    pub fn encrypt(reaction: Reaction, sender: &libp2p::PeerId) -> anyhow::Result<Self> {
+        let content = Encoding::Cbor.encode(&ReactionContent {
+            message_id: reaction.message_id,
+            emoji: reaction.emoji,
+            added: reaction.added,
+            reacted_at: reaction.reacted_at,
+        })?;
+        let sender = sender.to_string();
+        let associated_data =
+            Self::associated_data(&sender, &reaction.participants, &reaction.chat_id)?;
+        Ok(Self {
+            sealed_content: crypto::seal(&content, &associated_data, &reaction.participants)?,
+            sender,
+            participants: reaction.participants,
+            chat_id: reaction.chat_id,
+        })
+    }
+
+    /// Fails if the message was not sent by `source`, the peer it was received from.
+    pub fn decrypt(
+        self,
+        source: &libp2p::PeerId,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<Reaction> {
+        check_sender(&self.sender, source)?;
+        let associated_data =
+            Self::associated_data(&self.sender, &self.participants, &self.chat_id)?;
+        let content: ReactionContent = Encoding::Cbor.decode(&crypto::open(
+            &self.sealed_content,
+            &associated_data,
+            keypair,
+        )?)?;
+        Ok(Reaction {
+            participants: self.participants,
+            chat_id: self.chat_id,
+            message_id: content.message_id,
+            emoji: content.emoji,
+            added: content.added,
+            reacted_at: content.reacted_at,
+        })
+    }
+
+    fn associated_data(
+        sender: &str,
+        participants: &[String],
+        chat_id: &uuid::Uuid,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(
+            "p2pc reaction",
+            sender,
+            participants,
+            chat_id,
+        ))?)
+    }
+}
+
+impl EncryptedChatMetadata {
+    pub fn encrypt(metadata: ChatMetadata, sender: &libp2p::PeerId) -> anyhow::Result<Self> {
+        let content = Encoding::Cbor.encode(&ChatMetadataContent {
+            name: metadata.name,
+            description: metadata.description,
+            icon: metadata.icon,
+            updated_at: metadata.updated_at,
+        })?;
+        let sender = sender.to_string();
+        let associated_data =
+            Self::associated_data(&sender, &metadata.participants, &metadata.chat_id)?;
+        Ok(Self {
+            sealed_content: crypto::seal(&content, &associated_data, &metadata.participants)?,
+            sender,
+            participants: metadata.participants,
+            chat_id: metadata.chat_id,
+        })
+    }
+
+    /// Fails if the message was not sent by `source`, the peer it was received from.
+    pub fn decrypt(
+        self,
+        source: &libp2p::PeerId,
+        keypair: &libp2p::identity::Keypair,
+    ) -> anyhow::Result<ChatMetadata> {
+        check_sender(&self.sender, source)?;
+        let associated_data =
+            Self::associated_data(&self.sender, &self.participants, &self.chat_id)?;
+        let content: ChatMetadataContent = Encoding::Cbor.decode(&crypto::open(
+            &self.sealed_content,
+            &associated_data,
+            keypair,
+        )?)?;
+        Ok(ChatMetadata {
+            participants: self.participants,
+            chat_id: self.chat_id,
+            name: content.name,
+            description: content.description,
+            icon: content.icon,
+            updated_at: content.updated_at,
+        })
+    }
+
+    fn associated_data(
+        sender: &str,
+        participants: &[String],
+        chat_id: &uuid::Uuid,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(
+            "p2pc chat metadata",
+            sender,
+            participants,
+            chat_id,
+        ))?)
+    }
+}
+
+/// The sender of an encrypted message is authenticated along with its content, so it only has to
+/// match the peer that sent it to us.
+fn check_sender(sender: &str, source: &libp2p::PeerId) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        *sender == source.to_string(),
+        "message of {sender} was sent by {source}"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope_content(encoding: Encoding, envelope: serde_json::Value) -> EnvelopeContent {
+        encoding
+            .decode_envelope(&encoding.encode(&envelope).unwrap())
+            .unwrap()
+            .content
+    }
+
+    #[test]
+    fn envelopes_survive_both_encodings() {
+        let message_id = uuid::Uuid::new_v4();
+        let envelope = Envelope::new(WireMessage::ReadReceipt(ReadReceipt {
+            message_id,
+            chat_id: uuid::Uuid::new_v4(),
+        }));
+        for encoding in [Encoding::Json, Encoding::Cbor] {
+            let bytes = encoding.encode(&envelope).unwrap();
+            assert_eq!(Encoding::detect(&bytes), encoding);
+            let decoded = encoding.decode_envelope(&bytes).unwrap();
+            assert_eq!(decoded.version, PROTOCOL_VERSION);
+            let EnvelopeContent::Known(WireMessage::ReadReceipt(read_receipt)) = decoded.content
+            else {
+                panic!("decoded {:?}", decoded.content);
+            };
+            assert_eq!(read_receipt.message_id, message_id);
+        }
+    }
+
+    #[test]
+    fn unknown_kinds_are_told_apart_from_malformed_payloads() {
+        for encoding in [Encoding::Json, Encoding::Cbor] {
+            let unknown = envelope_content(
+                encoding,
+                serde_json::json!({"version": 3, "kind": "Poll", "payload": {"question": "?"}}),
+            );
+            assert!(matches!(unknown, EnvelopeContent::Unknown { kind } if kind == "Poll"));
+
+            let malformed = envelope_content(
+                encoding,
+                serde_json::json!({"version": 2, "kind": "Typing", "payload": {"chat_id": 5}}),
+            );
+            assert!(
+                matches!(malformed, EnvelopeContent::Malformed { kind, .. } if kind == "Typing")
+            );
+
+            let missing_payload =
+                envelope_content(encoding, serde_json::json!({"version": 2, "kind": "Ack"}));
+            assert!(
+                matches!(missing_payload, EnvelopeContent::Malformed { kind, .. } if kind == "Ack")
+            );
+        }
+    }
+
+    #[test]
+    fn envelopes_without_kind_are_rejected() {
+        for encoding in [Encoding::Json, Encoding::Cbor] {
+            let bytes = encoding
+                .encode(&serde_json::json!({"version": 2, "payload": {}}))
+                .unwrap();
+            assert!(encoding.decode_envelope(&bytes).is_err());
+        }
+    }
+
+    fn chat_message(participants: Vec<String>) -> ChatMessage {
+        ChatMessage {
+            participants,
+            content: "hello".to_string(),
+            id: uuid::Uuid::new_v4(),
+            chat_id: uuid::Uuid::new_v4(),
+            answer_to: None,
+            sent_at: std::time::SystemTime::now(),
+            attachment: None,
+        }
+    }
+
+    #[test]
+    fn encrypted_chat_message_round_trip() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let bob_id = libp2p::PeerId::random();
+        let message = chat_message(vec![alice.public().to_peer_id().to_string()]);
+        let (id, chat_id) = (message.id, message.chat_id);
+        let decrypted = EncryptedChatMessage::encrypt(message, &bob_id)
+            .unwrap()
+            .decrypt(&bob_id, &alice)
+            .unwrap();
+        assert_eq!(decrypted.content, "hello");
+        assert_eq!((decrypted.id, decrypted.chat_id), (id, chat_id));
+    }
+
+    #[test]
+    fn changed_plaintext_fields_are_rejected() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let bob = libp2p::identity::Keypair::generate_ed25519();
+        let bob_id = bob.public().to_peer_id();
+        let encrypted = EncryptedChatMessage::encrypt(
+            chat_message(vec![
+                alice.public().to_peer_id().to_string(),
+                bob_id.to_string(),
+            ]),
+            &bob_id,
+        )
+        .unwrap();
+
+        let mut moved = encrypted.clone();
+        moved.chat_id = uuid::Uuid::new_v4();
+        assert!(moved.decrypt(&bob_id, &alice).is_err());
+
+        let mut replayed = encrypted.clone();
+        replayed.id = uuid::Uuid::new_v4();
+        assert!(replayed.decrypt(&bob_id, &alice).is_err());
+
+        let mut narrowed = encrypted;
+        narrowed.participants.pop();
+        assert!(narrowed.decrypt(&bob_id, &alice).is_err());
+    }
+
+    #[test]
+    fn messages_of_others_are_rejected() {
+        let alice = libp2p::identity::Keypair::generate_ed25519();
+        let bob_id = libp2p::identity::Keypair::generate_ed25519()
+            .public()
+            .to_peer_id();
+        let mallory_id = libp2p::identity::Keypair::generate_ed25519()
+            .public()
+            .to_peer_id();
+        let participants = vec![alice.public().to_peer_id().to_string(), bob_id.to_string()];
+
+        // someone forwarding the message of a participant as their own
+        let encrypted =
+            EncryptedChatMessage::encrypt(chat_message(participants.clone()), &bob_id).unwrap();
+        assert!(encrypted.decrypt(&mallory_id, &alice).is_err());
+
+        // a participant claiming someone else sent their message
+        let mut forged =
+            EncryptedChatMessage::encrypt(chat_message(participants), &mallory_id).unwrap();
+        forged.sender = bob_id.to_string();
+        assert!(forged.decrypt(&bob_id, &alice).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use super::Chat;
 use std::collections::HashMap;
+use std::time::Instant;
 
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 pub struct Chats {
@@ -12,7 +13,11 @@ impl Chats {
     }
 
     pub fn get_chat(&self, chat_id: &uuid::Uuid) -> Option<&Chat> {
-        self.chats.get(&chat_id)
+        self.chats.get(chat_id)
+    }
+
+    pub fn get_chat_mut(&mut self, chat_id: &uuid::Uuid) -> Option<&mut Chat> {
+        self.chats.get_mut(chat_id)
     }
 
     pub fn get_chats(&self) -> &HashMap<uuid::Uuid, Chat> {
@@ -31,8 +36,51 @@ impl Chats {
         message: String,
         answer_to: Option<uuid::Uuid>,
     ) {
-        if let Some(chat) = self.chats.get_mut(&chat_id) {
+        if let Some(chat) = self.chats.get_mut(chat_id) {
             chat.send_message(p2pc, sender_id, message, answer_to);
         }
     }
+
+    pub fn send_file(
+        &mut self,
+        chat_id: &uuid::Uuid,
+        p2pc: &mut p2pc_lib::P2pc,
+        sender_id: String,
+        path: &std::path::Path,
+        attachment: p2pc_lib::Attachment,
+    ) -> anyhow::Result<()> {
+        match self.chats.get_mut(chat_id) {
+            Some(chat) => chat.send_file(p2pc, sender_id, path, attachment),
+            None => Ok(()),
+        }
+    }
+
+    pub fn share_files(&self, p2pc: &mut p2pc_lib::P2pc, sender_id: &str) {
+        for chat in self.chats.values() {
+            chat.share_files(p2pc, sender_id);
+        }
+    }
+
+    pub fn set_attachment_path(&mut self, file_id: &uuid::Uuid, path: std::path::PathBuf) {
+        for chat in self.chats.values_mut() {
+            if chat.set_attachment_path(file_id, path.clone()) {
+                return;
+            }
+        }
+    }
+
+    /// Sends the pending messages of all chats that are due and returns when the next one is due.
+    pub fn send_pending_messages(&mut self, p2pc: &mut p2pc_lib::P2pc) -> Option<Instant> {
+        let now = Instant::now();
+        self.chats
+            .values_mut()
+            .filter_map(|chat| chat.send_pending_messages(p2pc, now))
+            .min()
+    }
+
+    pub fn send_pending_messages_now(&mut self) {
+        for chat in self.chats.values_mut() {
+            chat.send_pending_messages_now();
+        }
+    }
 }
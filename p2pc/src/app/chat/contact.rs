@@ -1,3 +1,4 @@
+use chrono::{DateTime, Local};
 use random_color::{Luminosity, RandomColor};
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -5,6 +6,9 @@ pub struct Contact {
     pub public_key: String,
     pub name: String,
     pub color: egui::Color32,
+    /// Last time we were connected to the contact.
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Local>>,
 }
 
 impl Default for Contact {
@@ -13,6 +17,7 @@ impl Default for Contact {
             public_key: String::new(),
             name: String::new(),
             color: egui::Color32::from_rgb(0, 0, 0),
+            last_seen: None,
         }
     }
 }
@@ -28,6 +33,7 @@ impl Contact {
                 (contact_window.color[2] * 255.) as u8,
                 255,
             ),
+            last_seen: None,
         }
     }
 }
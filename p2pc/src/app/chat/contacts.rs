@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use chrono::{DateTime, Local};
 use super::Contact;
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -22,6 +23,12 @@ impl Contacts {
         &self.contacts
     }
 
+    pub fn set_last_seen(&mut self, public_key: &str, last_seen: DateTime<Local>) {
+        if let Some(contact) = self.contacts.get_mut(public_key) {
+            contact.last_seen = Some(last_seen);
+        }
+    }
+
     pub fn remove_contact(&mut self, public_key: &str) {
         self.contacts.remove(public_key);
     }
@@ -1,6 +1,38 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
 use chrono::{DateTime, Local};
 use uuid::Uuid;
 
+const INITIAL_SEND_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_SEND_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum MessageStatus {
+    /// Not handed to the network yet, e.g. because no peers were reachable. Resent with backoff.
+    Pending,
+    #[default]
+    Sent,
+    /// Sending failed for a reason retrying will not fix. Only resent on request.
+    Failed,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Deletion {
+    /// Hidden locally, the other participants still have the message.
+    ForMe,
+    /// Withdrawn by its sender for all participants, shown as a tombstone.
+    ForEveryone,
+}
+
+/// Content of a message before it was edited.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct PreviousVersion {
+    pub content: String,
+    /// When the content was replaced by the next version.
+    pub replaced_at: DateTime<Local>,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Message {
     sender: String,
@@ -8,26 +40,78 @@ pub struct Message {
     content: String,
     answer_to: Option<uuid::Uuid>,
     pub recieved_time: DateTime<Local>,
+    #[serde(default)]
+    delivered_to: Vec<String>,
+    #[serde(default)]
+    attachment: Option<p2pc_lib::Attachment>,
+    /// Local copy of the attachment: the shared file for our own messages, the downloaded file for
+    /// received ones.
+    #[serde(default)]
+    attachment_path: Option<PathBuf>,
+    #[serde(default)]
+    status: MessageStatus,
+    /// Time of the last edit applied to the content.
+    #[serde(default)]
+    edited_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    previous_versions: Vec<PreviousVersion>,
+    #[serde(default)]
+    deletion: Option<Deletion>,
+    #[serde(skip)]
+    send_attempts: u32,
+    /// When to resend a pending message, `None` if it is due.
+    #[serde(skip)]
+    next_send_attempt: Option<Instant>,
 }
 
 impl Message {
-    pub fn new(sender_id: String, message: String, answer_to: Option<Uuid>) -> Self {
+    pub fn new(
+        sender_id: String,
+        message: String,
+        answer_to: Option<Uuid>,
+        attachment: Option<p2pc_lib::Attachment>,
+    ) -> Self {
         Self {
             sender: sender_id,
             message_id: uuid::Uuid::new_v4(),
             content: message,
             answer_to,
             recieved_time: Local::now(),
+            delivered_to: Vec::new(),
+            attachment,
+            attachment_path: None,
+            status: MessageStatus::Pending,
+            edited_at: None,
+            previous_versions: Vec::new(),
+            deletion: None,
+            send_attempts: 0,
+            next_send_attempt: None,
         }
     }
 
-    pub fn new_with_id(sender_id: String, message: String, answer_to: Option<Uuid>, message_id: Uuid) -> Self {
+    pub fn new_with_id(
+        sender_id: String,
+        message: String,
+        answer_to: Option<Uuid>,
+        message_id: Uuid,
+        sent_time: DateTime<Local>,
+        attachment: Option<p2pc_lib::Attachment>,
+    ) -> Self {
         Self {
             sender: sender_id,
             message_id,
             content: message,
             answer_to,
-            recieved_time: Local::now(),
+            recieved_time: sent_time,
+            delivered_to: Vec::new(),
+            attachment,
+            attachment_path: None,
+            status: MessageStatus::Sent,
+            edited_at: None,
+            previous_versions: Vec::new(),
+            deletion: None,
+            send_attempts: 0,
+            next_send_attempt: None,
         }
     }
 
@@ -46,4 +130,133 @@ impl Message {
     pub fn get_answer_to(&self) -> Option<&uuid::Uuid> {
         self.answer_to.as_ref()
     }
+
+    /// Replaces the content unless the message was deleted or a later edit was applied already.
+    /// Returns whether the edit was applied.
+    pub fn edit(&mut self, content: String, edited_at: DateTime<Local>) -> bool {
+        if self.deletion.is_some()
+            || self
+                .edited_at
+                .is_some_and(|last_edit| last_edit >= edited_at)
+        {
+            return false;
+        }
+        let previous_content = std::mem::replace(&mut self.content, content);
+        self.previous_versions.push(PreviousVersion {
+            content: previous_content,
+            replaced_at: edited_at,
+        });
+        self.edited_at = Some(edited_at);
+        true
+    }
+
+    pub fn get_edited_at(&self) -> Option<&DateTime<Local>> {
+        self.edited_at.as_ref()
+    }
+
+    pub fn get_previous_versions(&self) -> &[PreviousVersion] {
+        &self.previous_versions
+    }
+
+    /// Drops the content, the attachment and the edit history. The message id is kept, so the
+    /// message is not fetched again by a history sync.
+    pub fn delete(&mut self, deletion: Deletion) {
+        self.content.clear();
+        self.attachment = None;
+        self.attachment_path = None;
+        self.edited_at = None;
+        self.previous_versions.clear();
+        self.deletion = Some(deletion);
+    }
+
+    pub fn get_deletion(&self) -> Option<Deletion> {
+        self.deletion
+    }
+
+    /// A message that reached a participant was sent, even if no peers were reachable when we
+    /// tried. A failed message stays failed, an ack does not undo the error we reported.
+    pub fn mark_delivered(&mut self, participant: String) {
+        if self.status == MessageStatus::Pending {
+            self.status = MessageStatus::Sent;
+        }
+        if !self.delivered_to.contains(&participant) {
+            self.delivered_to.push(participant);
+        }
+    }
+
+    pub fn is_delivered_to(&self, participant: &str) -> bool {
+        self.delivered_to
+            .iter()
+            .any(|delivered_to| delivered_to == participant)
+    }
+
+    pub fn to_chat_message(
+        &self,
+        participants: Vec<String>,
+        chat_id: Uuid,
+    ) -> p2pc_lib::ChatMessage {
+        p2pc_lib::ChatMessage {
+            participants,
+            content: self.content.clone(),
+            id: self.message_id,
+            chat_id,
+            answer_to: self.answer_to,
+            sent_at: self.recieved_time.into(),
+            attachment: self.attachment.clone(),
+        }
+    }
+
+    pub fn get_attachment(&self) -> Option<&p2pc_lib::Attachment> {
+        self.attachment.as_ref()
+    }
+
+    pub fn get_attachment_path(&self) -> Option<&Path> {
+        self.attachment_path.as_deref()
+    }
+
+    pub fn set_attachment_path(&mut self, path: PathBuf) {
+        self.attachment_path = Some(path);
+    }
+
+    pub fn get_status(&self) -> MessageStatus {
+        self.status
+    }
+
+    /// Ignored once the message was delivered, the result of sending it may arrive after the ack.
+    pub fn set_status(&mut self, status: MessageStatus) {
+        if self.delivered_to.is_empty() {
+            self.status = status;
+        }
+    }
+
+    pub fn is_send_due(&self, now: Instant) -> bool {
+        self.status == MessageStatus::Pending
+            && self.deletion.is_none()
+            && self
+                .next_send_attempt
+                .map_or(true, |next_send_attempt| next_send_attempt <= now)
+    }
+
+    pub fn get_next_send_attempt(&self) -> Option<Instant> {
+        match self.status {
+            MessageStatus::Pending => self.next_send_attempt,
+            _ => None,
+        }
+    }
+
+    /// Schedules the next attempt with exponential backoff, in case this one fails as well.
+    pub fn record_send_attempt(&mut self, now: Instant) {
+        let backoff = INITIAL_SEND_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.send_attempts))
+            .min(MAX_SEND_BACKOFF);
+        self.send_attempts = self.send_attempts.saturating_add(1);
+        self.next_send_attempt = Some(now + backoff);
+    }
+
+    /// Resends the message as soon as possible, also if sending it failed before.
+    pub fn send_now(&mut self) {
+        self.status = MessageStatus::Pending;
+        self.send_attempts = 0;
+        self.next_send_attempt = None;
+    }
 }
@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use p2pc_lib::{MembershipChange, MembershipOperation};
+
 use uuid::Uuid;
 mod message;
 pub use message::*;
@@ -11,12 +18,43 @@ pub use contacts::*;
 mod chats;
 pub use chats::*;
 
+mod reaction;
+pub use reaction::*;
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Chat {
     chat_id: Uuid,
     pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Encoded in any common image format. Empty if the chat has no icon.
+    #[serde(default)]
+    icon: Vec<u8>,
+    /// Time and author of the latest change of name, description and icon.
+    #[serde(default)]
+    metadata_version: Option<(DateTime<Local>, String)>,
     messages: Vec<Message>,
     participants: Vec<String>,
+    /// Latest message every participant has read, including everything before it.
+    #[serde(default)]
+    last_read: HashMap<String, Uuid>,
+    /// Latest message we told the other participants we read.
+    #[serde(default)]
+    own_last_read: Option<Uuid>,
+    /// Reactions to messages, keyed by message id.
+    #[serde(default)]
+    reactions: HashMap<Uuid, Vec<Reaction>>,
+    /// Every membership operation of the chat with a valid signature, the participants follow
+    /// from them.
+    #[serde(default)]
+    membership_operations: Vec<MembershipOperation>,
+    /// Members the chat had before the first membership operation. Chats we were added to only
+    /// know the author of the first operation.
+    #[serde(default)]
+    founders: Vec<String>,
+    /// We left the chat or were removed from it.
+    #[serde(default)]
+    left: bool,
 }
 
 impl Chat {
@@ -27,24 +65,525 @@ impl Chat {
         message: String,
         answer_to: Option<Uuid>,
     ) {
-        let ui_message = Message::new(sender_id, message.clone(), answer_to);
-        if p2pc
-            .execute(p2pc_lib::Action::SendMessage(p2pc_lib::ChatMessage {
+        self.messages
+            .push(Message::new(sender_id, message, answer_to, None));
+        self.send_pending_messages(p2pc, Instant::now());
+    }
+
+    /// Describes the file at `path` to send it with [`Self::send_file`]. Reads the whole file, so
+    /// it is not meant to be called on the UI thread.
+    pub fn prepare_attachment(path: &Path) -> anyhow::Result<p2pc_lib::Attachment> {
+        let mut attachment = p2pc_lib::Attachment::from_file(path)?;
+        attachment.thumbnail = crate::app::images::create_thumbnail(path).unwrap_or_default();
+        Ok(attachment)
+    }
+
+    /// Shares the file at `path` with the participants and sends a message referencing it.
+    pub fn send_file(
+        &mut self,
+        p2pc: &mut p2pc_lib::P2pc,
+        sender_id: String,
+        path: &Path,
+        attachment: p2pc_lib::Attachment,
+    ) -> anyhow::Result<()> {
+        p2pc.execute(p2pc_lib::Action::ShareFile {
+            attachment: attachment.clone(),
+            path: path.to_path_buf(),
+            peers: self.participants.clone(),
+        })
+        .map_err(|_| anyhow::anyhow!("p2pc is not running"))?;
+        let mut message = Message::new(sender_id, attachment.name.clone(), None, Some(attachment));
+        message.set_attachment_path(path.to_path_buf());
+        self.messages.push(message);
+        self.send_pending_messages(p2pc, Instant::now());
+        Ok(())
+    }
+
+    /// Shares the files attached to our messages again, shared files are forgotten on restart.
+    pub fn share_files(&self, p2pc: &mut p2pc_lib::P2pc, sender_id: &str) {
+        for message in self
+            .messages
+            .iter()
+            .filter(|message| message.get_sender() == sender_id)
+        {
+            if let (Some(attachment), Some(path)) =
+                (message.get_attachment(), message.get_attachment_path())
+            {
+                p2pc.execute(p2pc_lib::Action::ShareFile {
+                    attachment: attachment.clone(),
+                    path: path.to_path_buf(),
+                    peers: self.participants.clone(),
+                })
+                .ok();
+            }
+        }
+    }
+
+    pub fn set_attachment_path(&mut self, file_id: &Uuid, path: PathBuf) -> bool {
+        match self.messages.iter_mut().find(|message| {
+            message
+                .get_attachment()
+                .is_some_and(|attachment| attachment.file_id == *file_id)
+        }) {
+            Some(message) => {
+                message.set_attachment_path(path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the pending messages that are due and returns when the next one is due.
+    pub fn send_pending_messages(
+        &mut self,
+        p2pc: &mut p2pc_lib::P2pc,
+        now: Instant,
+    ) -> Option<Instant> {
+        for message in self
+            .messages
+            .iter_mut()
+            .filter(|message| message.is_send_due(now))
+        {
+            if p2pc
+                .execute(p2pc_lib::Action::SendMessage(
+                    message.to_chat_message(self.participants.clone(), self.chat_id),
+                ))
+                .is_ok()
+            {
+                message.record_send_attempt(now);
+            }
+        }
+        self.messages
+            .iter()
+            .filter_map(Message::get_next_send_attempt)
+            .min()
+    }
+
+    /// Makes all pending messages due, e.g. because a peer connected.
+    pub fn send_pending_messages_now(&mut self) {
+        for message in self.messages.iter_mut() {
+            if message.get_status() == MessageStatus::Pending {
+                message.send_now();
+            }
+        }
+    }
+
+    /// Edits one of our messages and sends the new content to the participants.
+    pub fn edit_message(&mut self, p2pc: &mut p2pc_lib::P2pc, message_id: &Uuid, content: String) {
+        let Some(index) = self.get_message_index(message_id) else {
+            return;
+        };
+        let edited_at = Local::now();
+        if self.messages[index].edit(content.clone(), edited_at) {
+            p2pc.execute(p2pc_lib::Action::EditMessage(p2pc_lib::MessageEdit {
                 participants: self.participants.clone(),
-                content: message,
-                id: *ui_message.get_message_id(),
+                message_id: *message_id,
                 chat_id: self.chat_id,
-                answer_to,
+                content,
+                edited_at: edited_at.into(),
             }))
-            .is_ok()
+            .ok();
+        }
+    }
+
+    /// Applies an edit by `editor`, which has to be the sender of the message. Returns whether the
+    /// edit was applied.
+    pub fn apply_edit(
+        &mut self,
+        message_id: &Uuid,
+        editor: &str,
+        content: String,
+        edited_at: DateTime<Local>,
+    ) -> bool {
+        match self.get_message_index(message_id) {
+            Some(index) if self.messages[index].get_sender() == editor => {
+                self.messages[index].edit(content, edited_at)
+            }
+            _ => false,
+        }
+    }
+
+    /// Deletes a message only for us.
+    pub fn delete_message_for_me(&mut self, message_id: &Uuid) {
+        if let Some(index) = self.get_message_index(message_id) {
+            self.messages[index].delete(Deletion::ForMe);
+        }
+    }
+
+    /// Withdraws one of our messages for all participants.
+    pub fn retract_message(&mut self, p2pc: &mut p2pc_lib::P2pc, message_id: &Uuid) {
+        if let Some(index) = self.get_message_index(message_id) {
+            self.messages[index].delete(Deletion::ForEveryone);
+            p2pc.execute(p2pc_lib::Action::RetractMessage {
+                message_id: *message_id,
+                chat_id: self.chat_id,
+                participants: self.participants.clone(),
+            })
+            .ok();
+        }
+    }
+
+    /// Applies a retraction signed by `author`, which has to be the sender of the message.
+    /// Returns whether the message was withdrawn.
+    pub fn apply_retraction(&mut self, message_id: &Uuid, author: &str) -> bool {
+        match self.get_message_index(message_id) {
+            Some(index) if self.messages[index].get_sender() == author => {
+                // messages we deleted for us stay hidden
+                if self.messages[index].get_deletion().is_none() {
+                    self.messages[index].delete(Deletion::ForEveryone);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds our reaction with `emoji` to a message, or takes it back if we reacted with it already.
+    pub fn toggle_reaction(
+        &mut self,
+        p2pc: &mut p2pc_lib::P2pc,
+        own_id: &str,
+        message_id: &Uuid,
+        emoji: String,
+    ) {
+        let added = !self
+            .reactions
+            .get(message_id)
+            .into_iter()
+            .flatten()
+            .any(|reaction| reaction.peer == own_id && reaction.emoji == emoji && reaction.added);
+        let reacted_at = Local::now();
+        self.apply_reaction(
+            message_id,
+            own_id.to_string(),
+            emoji.clone(),
+            added,
+            reacted_at,
+        );
+        p2pc.execute(p2pc_lib::Action::React(p2pc_lib::Reaction {
+            participants: self.participants.clone(),
+            chat_id: self.chat_id,
+            message_id: *message_id,
+            emoji,
+            added,
+            reacted_at: reacted_at.into(),
+        }))
+        .ok();
+    }
+
+    /// Records a reaction of `peer`, unless a later one with the same emoji is known already.
+    pub fn apply_reaction(
+        &mut self,
+        message_id: &Uuid,
+        peer: String,
+        emoji: String,
+        added: bool,
+        reacted_at: DateTime<Local>,
+    ) {
+        let reactions = self.reactions.entry(*message_id).or_default();
+        match reactions
+            .iter_mut()
+            .find(|reaction| reaction.peer == peer && reaction.emoji == emoji)
+        {
+            Some(reaction) => {
+                if reaction.reacted_at < reacted_at {
+                    reaction.added = added;
+                    reaction.reacted_at = reacted_at;
+                }
+            }
+            None => reactions.push(Reaction {
+                peer,
+                emoji,
+                added,
+                reacted_at,
+            }),
+        }
+    }
+
+    /// Emojis used to react to a message, sorted, with the peers that reacted with them.
+    pub fn get_reactions(&self, message_id: &Uuid) -> Vec<(String, Vec<String>)> {
+        let mut reactions: Vec<(String, Vec<String>)> = vec![];
+        for reaction in self
+            .reactions
+            .get(message_id)
+            .into_iter()
+            .flatten()
+            .filter(|reaction| reaction.added)
         {
-            self.messages.push(ui_message);
+            match reactions
+                .iter_mut()
+                .find(|(emoji, _)| *emoji == reaction.emoji)
+            {
+                Some((_, peers)) => peers.push(reaction.peer.clone()),
+                None => reactions.push((reaction.emoji.clone(), vec![reaction.peer.clone()])),
+            }
         }
+        reactions.sort_by(|(emoji, _), (other_emoji, _)| emoji.cmp(other_emoji));
+        reactions
     }
 
-    pub fn insert_message(&mut self, sender_id: String, message: String, answer_to: Option<Uuid>, message_id: Uuid) {
+    /// Changes name, description and icon of the chat and sends them to the participants.
+    pub fn set_metadata(
+        &mut self,
+        p2pc: &mut p2pc_lib::P2pc,
+        own_id: &str,
+        name: String,
+        description: String,
+        icon: Vec<u8>,
+    ) {
+        // our change has to win over the one we know even if the clock of its author is ahead
+        let updated_at = match &self.metadata_version {
+            Some((latest_updated_at, _)) => {
+                Local::now().max(*latest_updated_at + chrono::Duration::milliseconds(1))
+            }
+            None => Local::now(),
+        };
+        self.apply_metadata(own_id.to_string(), name, description, icon, updated_at);
+        if let Some(metadata) = self.get_metadata(own_id, self.participants.clone()) {
+            p2pc.execute(p2pc_lib::Action::UpdateChatMetadata(metadata))
+                .ok();
+        }
+    }
+
+    /// Applies a change of the metadata by `author`, unless a later one is known already. Changes
+    /// made at the same time are ordered by their authors, so all participants pick the same one.
+    /// Returns whether the change was applied.
+    pub fn apply_metadata(
+        &mut self,
+        author: String,
+        name: String,
+        description: String,
+        icon: Vec<u8>,
+        updated_at: DateTime<Local>,
+    ) -> bool {
+        if let Some((latest_updated_at, latest_author)) = &self.metadata_version {
+            if (*latest_updated_at, latest_author) >= (updated_at, &author) {
+                return false;
+            }
+        }
+        self.name = name;
+        self.description = description;
+        self.icon = icon;
+        self.metadata_version = Some((updated_at, author));
+        true
+    }
+
+    /// Our latest change of the metadata for `peers`, if the current metadata is from us.
+    pub fn get_metadata(&self, own_id: &str, peers: Vec<String>) -> Option<p2pc_lib::ChatMetadata> {
+        match &self.metadata_version {
+            Some((updated_at, author)) if author == own_id => Some(p2pc_lib::ChatMetadata {
+                participants: peers,
+                chat_id: self.chat_id,
+                name: self.name.clone(),
+                description: self.description.clone(),
+                icon: self.icon.clone(),
+                updated_at: (*updated_at).into(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn get_icon(&self) -> &[u8] {
+        &self.icon
+    }
+
+    /// Adds and removes members and sends the signed operations to all members, including the
+    /// removed ones. Removing ourselves leaves the chat.
+    pub fn change_members(
+        &mut self,
+        p2pc: &mut p2pc_lib::P2pc,
+        keypair: &libp2p::identity::Keypair,
+        changes: Vec<(String, MembershipChange)>,
+    ) -> anyhow::Result<()> {
+        let own_id = keypair.public().to_peer_id().to_string();
+        if self.membership_operations.is_empty() {
+            self.founders = self.get_members(&own_id);
+            // members added later only learn about the founders through operations, adding them
+            // all with the first clock lets them replay the operations like everyone else
+            for member in self.founders.clone() {
+                self.membership_operations.push(MembershipOperation::sign(
+                    self.chat_id,
+                    member,
+                    MembershipChange::Add,
+                    1,
+                    keypair,
+                )?);
+            }
+        }
+        let mut peers = self.participants.clone();
+        for (member, change) in changes {
+            let clock = self
+                .membership_operations
+                .iter()
+                .map(|operation| operation.clock)
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let operation =
+                MembershipOperation::sign(self.chat_id, member.clone(), change, clock, keypair)?;
+            self.membership_operations.push(operation);
+            if member != own_id && !peers.contains(&member) {
+                peers.push(member);
+            }
+        }
+        self.update_members(&own_id);
+        p2pc.execute(p2pc_lib::Action::SendMembershipOperations {
+            chat_id: self.chat_id,
+            operations: self.membership_operations.clone(),
+            peers,
+        })
+        .ok();
+        Ok(())
+    }
+
+    pub fn get_membership_operations(&self) -> &Vec<MembershipOperation> {
+        &self.membership_operations
+    }
+
+    /// Adds membership operations to the ones we know and updates the participants. Operations
+    /// of authors that may not change the members are dropped. Returns whether any of them was
+    /// new to us.
+    pub fn apply_membership_operations(
+        &mut self,
+        own_id: &str,
+        operations: Vec<MembershipOperation>,
+    ) -> bool {
+        if self.membership_operations.is_empty() && self.founders.is_empty() {
+            self.founders = self.get_members(own_id);
+        }
+        let mut known_operations = self.membership_operations.clone();
+        let new_operations = operations
+            .into_iter()
+            .filter(|operation| !known_operations.contains(operation))
+            .collect::<Vec<_>>();
+        known_operations.extend(new_operations.iter().cloned());
+        self.membership_operations =
+            p2pc_lib::allowed_operations(self.get_founders(), known_operations);
+        let applied = new_operations
+            .iter()
+            .any(|operation| self.membership_operations.contains(operation));
+        if applied {
+            self.update_members(own_id);
+        }
+        applied
+    }
+
+    /// Replays all membership operations, starting with the founders of the chat.
+    fn update_members(&mut self, own_id: &str) {
+        let members = p2pc_lib::members(self.get_founders(), &self.membership_operations);
+        self.left = !members.iter().any(|member| member == own_id);
+        self.participants = members
+            .into_iter()
+            .filter(|member| member != own_id)
+            .collect();
+    }
+
+    /// Falls back to the members the chat started with if we don't know the founders. The first
+    /// change of the members of a chat adds all founders with the first clock, so they are the
+    /// members added that way by an author that added itself.
+    fn get_founders(&self) -> Vec<String> {
+        if !self.founders.is_empty() {
+            return self.founders.clone();
+        }
+        let first_additions = self
+            .membership_operations
+            .iter()
+            .filter(|operation| operation.clock == 1 && operation.change == MembershipChange::Add)
+            .collect::<Vec<_>>();
+        let mut founders = Vec::new();
+        for operation in &first_additions {
+            let author_is_founder = first_additions.iter().any(|addition| {
+                addition.author == operation.author && addition.member == addition.author
+            });
+            if author_is_founder && !founders.contains(&operation.member) {
+                founders.push(operation.member.clone());
+            }
+        }
+        founders
+    }
+
+    /// Participants including ourselves, unless we left.
+    fn get_members(&self, own_id: &str) -> Vec<String> {
+        let mut members = self.participants.clone();
+        if !self.left {
+            members.push(own_id.to_string());
+        }
+        members
+    }
+
+    /// Whether we left the chat or were removed from it.
+    pub fn has_left(&self) -> bool {
+        self.left
+    }
+
+    pub fn retry_message(&mut self, message_id: &Uuid) {
+        if let Some(index) = self.get_message_index(message_id) {
+            self.messages[index].send_now();
+        }
+    }
+
+    pub fn set_message_status(&mut self, message_id: &Uuid, status: MessageStatus) {
+        if let Some(index) = self.get_message_index(message_id) {
+            self.messages[index].set_status(status);
+        }
+    }
+
+    pub fn insert_message(
+        &mut self,
+        sender_id: String,
+        message: String,
+        answer_to: Option<Uuid>,
+        message_id: Uuid,
+        sent_time: DateTime<Local>,
+        attachment: Option<p2pc_lib::Attachment>,
+    ) {
+        // a message can reach us on the chat topic, on our own peer topic and by a history sync
+        if self.get_message_from_id(&message_id).is_some() {
+            return;
+        }
+        // messages fetched by a history sync arrive late, keep the messages in the order they were sent in
+        let index = self
+            .messages
+            .iter()
+            .rposition(|message| message.recieved_time <= sent_time)
+            .map_or(0, |index| index + 1);
+        self.messages.insert(
+            index,
+            Message::new_with_id(
+                sender_id, message, answer_to, message_id, sent_time, attachment,
+            ),
+        );
+    }
+
+    /// Time the newest message of `sender_id` was sent at, to ask the sender for the messages we
+    /// missed since.
+    pub fn get_newest_message_time(&self, sender_id: &str) -> Option<std::time::SystemTime> {
         self.messages
-            .push(Message::new_with_id(sender_id, message.clone(), answer_to, message_id));
+            .iter()
+            .filter(|message| message.get_sender() == sender_id)
+            .map(|message| message.recieved_time)
+            .max()
+            .map(Into::into)
+    }
+
+    /// Messages sent by `sender_id` after `sent_after`, to answer a history sync.
+    pub fn get_messages_sent_after(
+        &self,
+        sender_id: &str,
+        sent_after: Option<std::time::SystemTime>,
+    ) -> Vec<p2pc_lib::ChatMessage> {
+        self.messages
+            .iter()
+            .filter(|message| {
+                message.get_sender() == sender_id
+                    && message.get_status() == MessageStatus::Sent
+                    && message.get_deletion().is_none()
+                    && sent_after.map_or(true, |sent_after| {
+                        std::time::SystemTime::from(message.recieved_time) > sent_after
+                    })
+            })
+            .map(|message| message.to_chat_message(self.participants.clone(), self.chat_id))
+            .collect()
     }
 
     pub fn new_chat(participants: Vec<String>) -> Self {
@@ -52,7 +591,16 @@ impl Chat {
             chat_id: Uuid::new_v4(),
             messages: Vec::new(),
             name: "New Chat".to_string(),
+            description: String::new(),
+            icon: Vec::new(),
+            metadata_version: None,
             participants,
+            last_read: HashMap::new(),
+            own_last_read: None,
+            reactions: HashMap::new(),
+            membership_operations: Vec::new(),
+            founders: Vec::new(),
+            left: false,
         }
     }
 
@@ -61,8 +609,59 @@ impl Chat {
             chat_id,
             messages: Vec::new(),
             name: "New Chat".to_string(),
+            description: String::new(),
+            icon: Vec::new(),
+            metadata_version: None,
             participants,
+            last_read: HashMap::new(),
+            own_last_read: None,
+            reactions: HashMap::new(),
+            membership_operations: Vec::new(),
+            founders: Vec::new(),
+            left: false,
+        }
+    }
+
+    /// Creates a chat for the first message we get in it. Only the sender and us are known to be
+    /// members, so the message has to name both as participants.
+    pub fn new_chat_from_message(
+        mut participants: Vec<String>,
+        chat_id: Uuid,
+        sender_id: &str,
+        own_id: &str,
+    ) -> Option<Self> {
+        if !participants
+            .iter()
+            .any(|participant| participant == sender_id)
+            || !participants.iter().any(|participant| participant == own_id)
+        {
+            return None;
         }
+        participants.retain(|participant| participant != own_id);
+        Some(Self::new_incoming_chat(participants, chat_id))
+    }
+
+    /// Creates a chat we may have been added to. We only know the members the chat started with
+    /// from the first change of its members.
+    pub fn new_chat_from_membership_operations(
+        chat_id: Uuid,
+        own_id: &str,
+        operations: Vec<MembershipOperation>,
+    ) -> Self {
+        let mut chat = Self::new_incoming_chat(Vec::new(), chat_id);
+        chat.left = true;
+        for operation in operations {
+            if !chat.membership_operations.contains(&operation) {
+                chat.membership_operations.push(operation);
+            }
+        }
+        chat.founders = chat.get_founders();
+        chat.membership_operations = p2pc_lib::allowed_operations(
+            chat.founders.clone(),
+            std::mem::take(&mut chat.membership_operations),
+        );
+        chat.update_members(own_id);
+        chat
     }
 
     pub fn get_chat_messages(&self) -> &Vec<Message> {
@@ -79,6 +678,75 @@ impl Chat {
             .find(|message| message.get_message_id() == message_id)
     }
 
+    pub fn mark_delivered(&mut self, message_id: &Uuid, participant: String) {
+        if let Some(message) = self
+            .messages
+            .iter_mut()
+            .find(|message| message.get_message_id() == message_id)
+        {
+            message.mark_delivered(participant);
+        }
+    }
+
+    /// Records that `participant` read all messages up to `message_id`. Receipts for older
+    /// messages than the one already recorded are ignored.
+    pub fn mark_read(&mut self, message_id: &Uuid, participant: String) {
+        let Some(read_index) = self.get_message_index(message_id) else {
+            return;
+        };
+        if let Some(last_read_index) = self
+            .last_read
+            .get(&participant)
+            .and_then(|last_read| self.get_message_index(last_read))
+        {
+            if last_read_index >= read_index {
+                return;
+            }
+        }
+        self.last_read.insert(participant, *message_id);
+    }
+
+    /// Index of the latest message read by each participant in [`Self::get_chat_messages`]. A
+    /// participant has read every message up to it.
+    pub fn get_last_read_indices(&self) -> HashMap<&str, usize> {
+        self.last_read
+            .iter()
+            .filter_map(|(participant, last_read)| {
+                Some((participant.as_str(), self.get_message_index(last_read)?))
+            })
+            .collect()
+    }
+
+    /// Tells the other participants that we read the chat up to its latest message.
+    pub fn send_read_receipt(&mut self, p2pc: &mut p2pc_lib::P2pc) {
+        let Some(latest_message_id) = self
+            .messages
+            .last()
+            .map(|message| *message.get_message_id())
+        else {
+            return;
+        };
+        if self.own_last_read == Some(latest_message_id) {
+            return;
+        }
+        if p2pc
+            .execute(p2pc_lib::Action::SendReadReceipt {
+                message_id: latest_message_id,
+                chat_id: self.chat_id,
+                participants: self.participants.clone(),
+            })
+            .is_ok()
+        {
+            self.own_last_read = Some(latest_message_id);
+        }
+    }
+
+    fn get_message_index(&self, message_id: &Uuid) -> Option<usize> {
+        self.messages
+            .iter()
+            .position(|message| message.get_message_id() == message_id)
+    }
+
     pub fn get_participants(&self) -> &Vec<String> {
         &self.participants
     }
@@ -88,6 +756,10 @@ impl Default for ChatEditWindowContent {
     fn default() -> Self {
         Self {
             name: "".to_string(),
+            description: "".to_string(),
+            icon: Vec::new(),
+            icon_path: "".to_string(),
+            icon_error: None,
             participants: Vec::new(),
         }
     }
@@ -95,6 +767,11 @@ impl Default for ChatEditWindowContent {
 
 pub struct ChatEditWindowContent {
     pub name: String,
+    pub description: String,
+    pub icon: Vec<u8>,
+    /// Path of the image to make the icon from.
+    pub icon_path: String,
+    pub icon_error: Option<String>,
     pub participants: Vec<String>,
 }
 
@@ -102,7 +779,209 @@ impl ChatEditWindowContent {
     pub fn from_chat(chat: &Chat) -> Self {
         Self {
             name: chat.name.clone(),
+            description: chat.description.clone(),
+            icon: chat.icon.clone(),
+            icon_path: "".to_string(),
+            icon_error: None,
             participants: chat.participants.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Peer {
+        keypair: libp2p::identity::Keypair,
+        id: String,
+    }
+
+    fn peer() -> Peer {
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let id = keypair.public().to_peer_id().to_string();
+        Peer { keypair, id }
+    }
+
+    /// Peers ordered by their ids, which break ties between operations with the same clock.
+    fn peers<const N: usize>() -> [Peer; N] {
+        let mut peers: [Peer; N] = std::array::from_fn(|_| peer());
+        peers.sort_by(|peer, other| peer.id.cmp(&other.id));
+        peers
+    }
+
+    fn operation(
+        chat_id: Uuid,
+        author: &Peer,
+        member: &Peer,
+        change: MembershipChange,
+        clock: u64,
+    ) -> MembershipOperation {
+        MembershipOperation::sign(chat_id, member.id.clone(), change, clock, &author.keypair)
+            .unwrap()
+    }
+
+    /// Operations of the first change of the members of a chat.
+    fn founding_operations(
+        chat_id: Uuid,
+        author: &Peer,
+        founders: &[&Peer],
+    ) -> Vec<MembershipOperation> {
+        founders
+            .iter()
+            .map(|founder| operation(chat_id, author, founder, MembershipChange::Add, 1))
+            .collect()
+    }
+
+    fn sorted_members(chat: &Chat, own: &Peer) -> Vec<String> {
+        let mut members = chat.get_members(&own.id);
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn concurrent_membership_changes_converge() {
+        let [alice, bob, carol, dave] = peers();
+        let chat_id = Uuid::new_v4();
+
+        // alice removes bob while bob adds dave, the removal wins as alice has the lower id
+        let mut alice_operations = founding_operations(chat_id, &alice, &[&alice, &bob, &carol]);
+        alice_operations.push(operation(
+            chat_id,
+            &alice,
+            &bob,
+            MembershipChange::Remove,
+            2,
+        ));
+        let mut bob_operations = founding_operations(chat_id, &bob, &[&alice, &bob, &carol]);
+        bob_operations.push(operation(chat_id, &bob, &dave, MembershipChange::Add, 2));
+
+        let mut carol_chat =
+            Chat::new_incoming_chat(vec![alice.id.clone(), bob.id.clone()], chat_id);
+        carol_chat.apply_membership_operations(&carol.id, alice_operations.clone());
+        carol_chat.apply_membership_operations(&carol.id, bob_operations.clone());
+
+        let mut other_carol_chat =
+            Chat::new_incoming_chat(vec![alice.id.clone(), bob.id.clone()], chat_id);
+        other_carol_chat.apply_membership_operations(&carol.id, bob_operations.clone());
+        assert!(other_carol_chat.get_participants().contains(&dave.id));
+        other_carol_chat.apply_membership_operations(&carol.id, alice_operations.clone());
+
+        // dave only hears of bob at first
+        let mut dave_chat =
+            Chat::new_chat_from_membership_operations(chat_id, &dave.id, bob_operations.clone());
+        assert!(!dave_chat.has_left());
+        dave_chat.apply_membership_operations(&dave.id, alice_operations.clone());
+        assert!(dave_chat.has_left());
+
+        let mut bob_chat =
+            Chat::new_incoming_chat(vec![alice.id.clone(), carol.id.clone()], chat_id);
+        bob_chat.apply_membership_operations(&bob.id, bob_operations);
+        bob_chat.apply_membership_operations(&bob.id, alice_operations);
+        assert!(bob_chat.has_left());
+
+        let expected = vec![alice.id.clone(), carol.id.clone()];
+        assert_eq!(sorted_members(&carol_chat, &carol), expected);
+        assert_eq!(sorted_members(&other_carol_chat, &carol), expected);
+        assert_eq!(sorted_members(&dave_chat, &dave), expected);
+        assert_eq!(sorted_members(&bob_chat, &bob), expected);
+    }
+
+    #[test]
+    fn messages_only_create_chats_of_their_sender_and_us() {
+        let [alice, bob, mallory] = peers();
+        let chat_id = Uuid::new_v4();
+        assert!(Chat::new_chat_from_message(
+            vec![alice.id.clone(), bob.id.clone()],
+            chat_id,
+            &mallory.id,
+            &alice.id,
+        )
+        .is_none());
+        assert!(Chat::new_chat_from_message(
+            vec![bob.id.clone(), mallory.id.clone()],
+            chat_id,
+            &mallory.id,
+            &alice.id,
+        )
+        .is_none());
+        let chat = Chat::new_chat_from_message(
+            vec![alice.id.clone(), bob.id.clone(), mallory.id.clone()],
+            chat_id,
+            &mallory.id,
+            &alice.id,
+        )
+        .unwrap();
+        assert_eq!(
+            chat.get_participants(),
+            &vec![bob.id.clone(), mallory.id.clone()]
+        );
+    }
+
+    #[test]
+    fn acks_do_not_undo_failures() {
+        let [alice, bob] = peers();
+        let mut chat = Chat::new_chat(vec![bob.id.clone()]);
+        chat.messages
+            .push(Message::new(alice.id.clone(), "hi".to_string(), None, None));
+        chat.messages.push(Message::new(
+            alice.id.clone(),
+            "bye".to_string(),
+            None,
+            None,
+        ));
+        let [pending, failed] = [0, 1].map(|index| *chat.messages[index].get_message_id());
+        chat.set_message_status(&failed, MessageStatus::Failed);
+
+        chat.mark_delivered(&pending, bob.id.clone());
+        chat.mark_delivered(&failed, bob.id.clone());
+        // the result of sending the message arrives after the ack
+        chat.set_message_status(&pending, MessageStatus::Pending);
+
+        let status = |message_id| chat.get_message_from_id(message_id).unwrap().get_status();
+        assert_eq!(status(&pending), MessageStatus::Sent);
+        assert_eq!(status(&failed), MessageStatus::Failed);
+    }
+
+    #[test]
+    fn operations_of_non_members_are_dropped() {
+        let [alice, bob, mallory] = peers();
+        let chat_id = Uuid::new_v4();
+        let mut chat = Chat::new_incoming_chat(vec![bob.id.clone()], chat_id);
+        let operations = vec![
+            operation(chat_id, &mallory, &mallory, MembershipChange::Add, 1),
+            operation(chat_id, &mallory, &bob, MembershipChange::Remove, 2),
+        ];
+        assert!(!chat.apply_membership_operations(&alice.id, operations));
+        assert_eq!(chat.get_participants(), &vec![bob.id.clone()]);
+        assert!(!chat.has_left());
+        assert!(chat.get_membership_operations().is_empty());
+
+        let addition = vec![operation(chat_id, &bob, &mallory, MembershipChange::Add, 1)];
+        assert!(chat.apply_membership_operations(&alice.id, addition.clone()));
+        // known operations are not applied again
+        assert!(!chat.apply_membership_operations(&alice.id, addition));
+    }
+
+    #[test]
+    fn foreign_chats_are_founded_by_their_first_members() {
+        let [alice, bob, carol, mallory] = peers();
+        let chat_id = Uuid::new_v4();
+        // mallory claims to have removed alice when the chat was founded
+        let mut operations = vec![operation(
+            chat_id,
+            &mallory,
+            &alice,
+            MembershipChange::Remove,
+            1,
+        )];
+        operations.extend(founding_operations(chat_id, &alice, &[&alice, &bob]));
+        operations.push(operation(chat_id, &alice, &carol, MembershipChange::Add, 2));
+        let chat = Chat::new_chat_from_membership_operations(chat_id, &carol.id, operations);
+        assert_eq!(chat.get_membership_operations().len(), 3);
+        assert_eq!(
+            sorted_members(&chat, &carol),
+            vec![alice.id.clone(), bob.id.clone(), carol.id.clone()]
+        );
+    }
+}
@@ -0,0 +1,11 @@
+use chrono::{DateTime, Local};
+
+/// Latest reaction of a peer with an emoji to a message.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Reaction {
+    pub peer: String,
+    pub emoji: String,
+    /// `false` if the peer took the reaction back.
+    pub added: bool,
+    pub reacted_at: DateTime<Local>,
+}
@@ -0,0 +1,212 @@
+// Thumbnails and textures of image attachments and chat icons.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use uuid::Uuid;
+
+const THUMBNAIL_SIZE: u32 = 160;
+const ICON_SIZE: u32 = 64;
+/// Thumbnails travel inside the encrypted chat message, which gossipsub limits to 64 KiB.
+pub const MAX_THUMBNAIL_BYTES: usize = 16 * 1024;
+/// Memory a decoder may use for a thumbnail we received, far more than a real one needs.
+const MAX_PREVIEW_ALLOC: u64 = 4 * 1024 * 1024;
+/// Larger images are scaled down for the viewer to stay within the texture size of the GPU.
+const MAX_IMAGE_SIZE: u32 = 2048;
+
+/// Creates a JPEG thumbnail if the file at `path` is an image.
+pub fn create_thumbnail(path: &Path) -> Option<Vec<u8>> {
+    create_preview(path, THUMBNAIL_SIZE)
+}
+
+/// Creates a chat icon from the image at `path`. Icons are sent like thumbnails.
+pub fn create_icon(path: &Path) -> Option<Vec<u8>> {
+    create_preview(path, ICON_SIZE)
+}
+
+fn create_preview(path: &Path, size: u32) -> Option<Vec<u8>> {
+    let image = image::io::Reader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .decode()
+        .ok()?;
+    let mut thumbnail = vec![];
+    image
+        .thumbnail(size, size)
+        .into_rgb8()
+        .write_to(
+            &mut std::io::Cursor::new(&mut thumbnail),
+            image::ImageOutputFormat::Jpeg(75),
+        )
+        .ok()?;
+    (thumbnail.len() <= MAX_THUMBNAIL_BYTES).then_some(thumbnail)
+}
+
+/// Decodes a thumbnail or icon made by a peer. One claiming to be much larger than the ones we
+/// make is rejected before it is decoded, so a small image cannot take up all our memory.
+fn decode_preview(preview: &[u8], size: u32) -> Option<image::DynamicImage> {
+    if preview.len() > MAX_THUMBNAIL_BYTES {
+        return None;
+    }
+    let mut reader = image::io::Reader::new(std::io::Cursor::new(preview))
+        .with_guessed_format()
+        .ok()?;
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(2 * size);
+    limits.max_image_height = Some(2 * size);
+    limits.max_alloc = Some(MAX_PREVIEW_ALLOC);
+    reader.limits(limits);
+    reader.decode().ok()
+}
+
+/// Textures of the images shown in the chat, decoded once per attachment. Images that cannot be
+/// decoded are remembered as `None`, so they are not decoded again every frame.
+#[derive(Default)]
+pub struct ImageCache {
+    thumbnails: HashMap<Uuid, Option<egui::TextureHandle>>,
+    images: HashMap<Uuid, Option<egui::TextureHandle>>,
+    /// Icons by chat id, with the encoded icon the texture was made from.
+    icons: HashMap<Uuid, (Vec<u8>, Option<egui::TextureHandle>)>,
+}
+
+impl ImageCache {
+    pub fn get_thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        attachment: &p2pc_lib::Attachment,
+    ) -> Option<egui::TextureHandle> {
+        if attachment.thumbnail.is_empty() {
+            return None;
+        }
+        self.thumbnails
+            .entry(attachment.file_id)
+            .or_insert_with(|| {
+                let image = decode_preview(&attachment.thumbnail, THUMBNAIL_SIZE)?;
+                Some(load_texture(
+                    ctx,
+                    &format!("thumbnail-{}", attachment.file_id),
+                    image,
+                ))
+            })
+            .clone()
+    }
+
+    /// The icon of a chat can change, so the texture is made again if `icon` differs from the
+    /// cached one.
+    pub fn get_icon(
+        &mut self,
+        ctx: &egui::Context,
+        chat_id: &Uuid,
+        icon: &[u8],
+    ) -> Option<egui::TextureHandle> {
+        if icon.is_empty() {
+            return None;
+        }
+        if let Some((cached_icon, texture)) = self.icons.get(chat_id) {
+            if cached_icon == icon {
+                return texture.clone();
+            }
+        }
+        let texture = decode_preview(icon, ICON_SIZE)
+            .map(|image| load_texture(ctx, &format!("icon-{}", chat_id), image));
+        self.icons
+            .insert(*chat_id, (icon.to_vec(), texture.clone()));
+        texture
+    }
+
+    pub fn get_image(
+        &mut self,
+        ctx: &egui::Context,
+        attachment: &p2pc_lib::Attachment,
+        path: &Path,
+    ) -> Option<egui::TextureHandle> {
+        self.images
+            .entry(attachment.file_id)
+            .or_insert_with(|| {
+                let image = image::io::Reader::open(path)
+                    .ok()?
+                    .with_guessed_format()
+                    .ok()?
+                    .decode()
+                    .ok()?;
+                let image = if image.width() > MAX_IMAGE_SIZE || image.height() > MAX_IMAGE_SIZE {
+                    image.resize(
+                        MAX_IMAGE_SIZE,
+                        MAX_IMAGE_SIZE,
+                        image::imageops::FilterType::Triangle,
+                    )
+                } else {
+                    image
+                };
+                Some(load_texture(
+                    ctx,
+                    &format!("image-{}", attachment.file_id),
+                    image,
+                ))
+            })
+            .clone()
+    }
+}
+
+fn load_texture(
+    ctx: &egui::Context,
+    name: &str,
+    image: image::DynamicImage,
+) -> egui::TextureHandle {
+    let image = image.into_rgba8();
+    ctx.load_texture(
+        name,
+        egui::ColorImage::from_rgba_unmultiplied(
+            [image.width() as usize, image.height() as usize],
+            image.as_raw(),
+        ),
+        egui::TextureOptions::LINEAR,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BMP image of the given size. Its pixels are left out if `with_pixels` is false.
+    fn bmp(width: u32, height: u32, with_pixels: bool) -> Vec<u8> {
+        let pixels = match with_pixels {
+            true => vec![0; (width * 3).next_multiple_of(4) as usize * height as usize],
+            false => Vec::new(),
+        };
+        let mut bmp = b"BM".to_vec();
+        bmp.extend((54 + pixels.len() as u32).to_le_bytes()); // file size
+        bmp.extend(0u32.to_le_bytes()); // reserved
+        bmp.extend(54u32.to_le_bytes()); // offset of the pixels
+        bmp.extend(40u32.to_le_bytes()); // size of the info header
+        bmp.extend(width.to_le_bytes());
+        bmp.extend(height.to_le_bytes());
+        bmp.extend(1u16.to_le_bytes()); // planes
+        bmp.extend(24u16.to_le_bytes()); // bits per pixel
+        bmp.extend([0; 24]); // no compression, default resolution and colors
+        bmp.extend(pixels);
+        bmp
+    }
+
+    #[test]
+    fn thumbnails_larger_than_ours_are_not_decoded() {
+        let wide = bmp(4 * THUMBNAIL_SIZE, 1, true);
+        assert!(image::load_from_memory(&wide).is_ok());
+        assert!(decode_preview(&wide, THUMBNAIL_SIZE).is_none());
+        assert!(decode_preview(&bmp(100_000, 100_000, false), THUMBNAIL_SIZE).is_none());
+    }
+
+    #[test]
+    fn thumbnails_we_make_are_decoded() {
+        let mut thumbnail = vec![];
+        image::DynamicImage::new_rgb8(THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2)
+            .write_to(
+                &mut std::io::Cursor::new(&mut thumbnail),
+                image::ImageOutputFormat::Jpeg(75),
+            )
+            .unwrap();
+        let image = decode_preview(&thumbnail, THUMBNAIL_SIZE).unwrap();
+        assert_eq!(image.width(), THUMBNAIL_SIZE);
+    }
+}
@@ -1,16 +1,22 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use egui::{vec2, Align, Button, Label, Layout, RichText, TextEdit};
 use egui::{Grid, ScrollArea};
 
+use libp2p::multiaddr::Protocol;
 use libp2p::Multiaddr;
+use p2pc_lib::MembershipChange;
 use uuid::Uuid;
 
 mod chat;
 use chat::Chat;
 use chat::Chats;
 use chat::Contacts;
+use chat::Deletion;
+use chat::MessageStatus;
 
+mod images;
 mod keypair_wrapper;
 
 use self::chat::{ChatEditWindowContent, Contact, ContactEditWindowContent};
@@ -24,8 +30,62 @@ struct CliArguments {
     peer_addresses: Vec<libp2p::Multiaddr>,
 
     /// Interfaces to listen on
-    #[arg(short, long, num_args(0..), value_name="MULTIADDRESS", default_values = vec!["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/0"])]
+    #[arg(short, long, num_args(0..), value_name="MULTIADDRESS", default_values = vec!["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/0", "/ip4/0.0.0.0/udp/0/quic-v1", "/ip6/::/udp/0/quic-v1"])]
     listen_addresses: Vec<libp2p::Multiaddr>,
+
+    /// Discover peers on the local network via mDNS
+    #[arg(long)]
+    mdns: bool,
+
+    /// Relay connections for peers behind NAT
+    #[arg(long)]
+    relay_server: bool,
+}
+
+/// State of the network, updated from the p2pc callback.
+#[derive(Clone, Default)]
+struct NetworkState {
+    listen_addresses: std::sync::Arc<std::sync::Mutex<Vec<Multiaddr>>>,
+    discovered_peers: std::sync::Arc<std::sync::Mutex<Vec<Multiaddr>>>,
+    /// DHT lookups of contacts, `None` while the lookup is running.
+    peer_lookups: std::sync::Arc<std::sync::Mutex<HashMap<String, Option<Vec<Multiaddr>>>>>,
+    /// Connected peers and the address of the first connection to them.
+    connected_peers: std::sync::Arc<std::sync::Mutex<HashMap<String, Multiaddr>>>,
+    /// Errors of the last attempt to dial an address.
+    dial_errors: std::sync::Arc<std::sync::Mutex<HashMap<Multiaddr, String>>>,
+    /// Errors of the last attempt to listen on an address.
+    listen_errors: std::sync::Arc<std::sync::Mutex<HashMap<Multiaddr, String>>>,
+    /// Running and failed downloads of attachments, keyed by file id.
+    downloads: std::sync::Arc<std::sync::Mutex<HashMap<Uuid, DownloadState>>>,
+    /// Participants typing in a chat, keyed by chat id and peer id, and when they last said so.
+    typing_peers: std::sync::Arc<std::sync::Mutex<HashMap<(Uuid, String), std::time::Instant>>>,
+    /// Presence connected peers announced last.
+    presences: std::sync::Arc<std::sync::Mutex<HashMap<String, p2pc_lib::Presence>>>,
+    /// Times peers were seen at, moved to their contacts on the next frame.
+    last_seen: std::sync::Arc<std::sync::Mutex<HashMap<String, chrono::DateTime<chrono::Local>>>>,
+}
+
+impl NetworkState {
+    /// Presence of a peer, `None` if it is offline. Connected peers that do not announce their
+    /// presence to us count as online.
+    fn get_presence(&self, peer: &str) -> Option<p2pc_lib::Presence> {
+        if !self.connected_peers.lock().unwrap().contains_key(peer) {
+            return None;
+        }
+        Some(
+            self.presences
+                .lock()
+                .unwrap()
+                .get(peer)
+                .copied()
+                .unwrap_or(p2pc_lib::Presence::Online),
+        )
+    }
+}
+
+enum DownloadState {
+    Running(f32),
+    Failed(String),
 }
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -34,6 +94,9 @@ struct CliArguments {
 pub struct App {
     current_message: String,
     current_message_answer_to: Option<Uuid>,
+    /// Message that is replaced by the current message when sending it.
+    #[serde(skip)]
+    current_message_edit_of: Option<Uuid>,
     auto_scroll: bool,
     current_chat_id: Option<uuid::Uuid>,
 
@@ -42,9 +105,11 @@ pub struct App {
     contacts: Contacts,
 
     #[serde(skip)]
-    listen_addresses: std::sync::Arc<std::sync::Mutex<Vec<Multiaddr>>>,
+    network: NetworkState,
+    /// Actions the p2pc callback wants to execute, executed on the next frame.
+    #[serde(skip)]
+    queued_actions: std::sync::Arc<std::sync::Mutex<Vec<p2pc_lib::Action>>>,
 
-    drop_chat_messages_from_unkown: bool,
     theme: Theme,
 
     settings: Settings,
@@ -69,12 +134,41 @@ pub struct App {
     edit_chat_mode: EditMode<uuid::Uuid>,
     #[serde(skip)]
     show_contacts: bool,
+    /// Path of the file to send, `Some` while the file panel is open.
+    #[serde(skip)]
+    current_file_path: Option<String>,
+    #[serde(skip)]
+    send_file_error: Option<String>,
+    /// Files to send whose attachment was described in the background, by chat id.
+    #[serde(skip)]
+    prepared_files: PreparedFiles,
+    #[serde(skip)]
+    images: images::ImageCache,
+    /// Message whose image is shown in the image viewer.
+    #[serde(skip)]
+    enlarged_image_message_id: Option<Uuid>,
+
+    /// Last time the user interacted with the app, to tell whether they are away.
+    #[serde(skip)]
+    last_input: std::time::Instant,
+    /// Presence and peers of the last [`p2pc_lib::Action::SetPresence`].
+    #[serde(skip)]
+    announced_presence: Option<(p2pc_lib::Presence, Vec<String>)>,
 
     #[serde(skip)]
     p2pc: Option<p2pc_lib::P2pc>,
 }
 
+/// Emojis offered in the context menu of a message.
+const REACTION_EMOJIS: [&str; 6] = ["👍", "❤", "😂", "😮", "😢", "🎉"];
+/// Longer reactions are ignored, they are shown inline under the message.
+const MAX_REACTION_CHARS: usize = 8;
+
+/// Time without input after which the user is shown as away to their contacts.
+const AWAY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
 #[derive(serde::Deserialize, serde::Serialize, PartialEq)]
+#[allow(clippy::upper_case_acronyms)] // variant names are part of the persisted app state
 enum Theme {
     LATTE,
     FRAPPE,
@@ -82,6 +176,16 @@ enum Theme {
     MOCHA,
 }
 
+type PreparedFiles = std::sync::Arc<
+    std::sync::Mutex<
+        Vec<(
+            Uuid,
+            std::path::PathBuf,
+            anyhow::Result<p2pc_lib::Attachment>,
+        )>,
+    >,
+>;
+
 #[derive(PartialEq, Clone, Copy)]
 enum EditMode<T> {
     None,
@@ -99,13 +203,18 @@ impl Default for App {
             show_chats: false,
             show_edit_chat: false,
             show_contacts: false,
+            current_file_path: None,
+            send_file_error: None,
+            prepared_files: Default::default(),
+            images: Default::default(),
+            enlarged_image_message_id: None,
             show_settings: false,
             settings: Settings::default(),
-            drop_chat_messages_from_unkown: false,
             chats: std::sync::Arc::new(std::sync::Mutex::new(Chats::default())),
             contacts: Contacts::default(),
             current_message: String::new(),
             current_message_answer_to: None,
+            current_message_edit_of: None,
             contact_edit_window_content: Default::default(),
             chat_edit_window_content: Default::default(),
             edit_chat_mode: EditMode::None,
@@ -113,31 +222,37 @@ impl Default for App {
             theme: Theme::MACCHIATO,
             p2pc: None,
             keypair: Default::default(),
-            listen_addresses: std::sync::Arc::new(std::sync::Mutex::new(vec![])),
+            network: Default::default(),
+            queued_actions: Default::default(),
+            last_input: std::time::Instant::now(),
+            announced_presence: None,
         }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Default)]
 struct Settings {
     peers: Vec<Multiaddr>,
     listen_addresses: Vec<Multiaddr>,
+    #[serde(default)]
+    mdns: bool,
+    /// Relays to reserve a slot on, so peers can reach us via `/p2p-circuit` addresses.
+    #[serde(default)]
+    relays: Vec<Multiaddr>,
+    #[serde(default)]
+    relay_server: bool,
+    /// Directory attachments are saved to, the user's download directory if empty.
+    #[serde(default)]
+    download_directory: String,
 
     #[serde(skip)]
     current_peer: String,
     #[serde(skip)]
     current_peer_is_valid: bool,
-}
-
-impl Default for Settings {
-    fn default() -> Self {
-        Self {
-            peers: Vec::new(),
-            listen_addresses: Vec::new(),
-            current_peer: String::new(),
-            current_peer_is_valid: false,
-        }
-    }
+    #[serde(skip)]
+    current_relay: String,
+    #[serde(skip)]
+    current_relay_is_valid: bool,
 }
 
 impl App {
@@ -151,30 +266,67 @@ impl App {
             None => Default::default(),
         };
 
+        let args = CliArguments::parse();
+        log::info!("{:?}", args);
+
         let mut p2pc = {
             let egui_ctx = cc.egui_ctx.clone();
-            let listen_addresses = app.listen_addresses.clone();
+            let network = app.network.clone();
+            let queued_actions = app.queued_actions.clone();
+            let local_peer_id = app.keypair.get_peer_id();
             let chats = app.chats.clone();
-            p2pc_lib::P2pc::new(app.keypair.get_keypair(), move |event| {
-                Self::handle_p2pc_event(event, &egui_ctx, listen_addresses.clone(), chats.clone())
+            let config = p2pc_lib::Config {
+                mdns: args.mdns || app.settings.mdns,
+                relay_server: args.relay_server || app.settings.relay_server,
+            };
+            p2pc_lib::P2pc::new(app.keypair.get_keypair(), config, move |event| {
+                Self::handle_p2pc_event(
+                    event,
+                    &egui_ctx,
+                    &network,
+                    chats.clone(),
+                    &queued_actions,
+                    &local_peer_id,
+                )
             })
             .expect("could not initialize p2pc")
         };
 
-        let args = CliArguments::parse();
-        log::info!("{:?}", args);
-
-        for address in args.peer_addresses {
-            p2pc.execute(p2pc_lib::Action::Dial(address)).ok();
-        }
-
-        for address in args.listen_addresses {
-            p2pc.execute(p2pc_lib::Action::ListenOn(address)).ok();
-        }
-
-        for address in &app.settings.peers {
-            p2pc.execute(p2pc_lib::Action::Dial(address.clone())).ok();
+        let listen_addresses = args.listen_addresses.into_iter().chain(
+            app.settings
+                .relays
+                .iter()
+                .map(|relay| relay.clone().with(Protocol::P2pCircuit)),
+        );
+        let bootstrap_addresses = args
+            .peer_addresses
+            .into_iter()
+            .chain(app.settings.peers.iter().cloned())
+            .collect();
+        let chat_ids: Vec<Uuid> = app
+            .chats
+            .lock()
+            .unwrap()
+            .get_chats()
+            .iter()
+            .filter(|(_, chat)| !chat.has_left())
+            .map(|(chat_id, _)| *chat_id)
+            .collect();
+        let actions = listen_addresses
+            .map(p2pc_lib::Action::ListenOn)
+            .chain(std::iter::once(p2pc_lib::Action::Bootstrap(
+                bootstrap_addresses,
+            )))
+            .chain(chat_ids.into_iter().map(p2pc_lib::Action::JoinChat));
+        for action in actions {
+            if let Err(error) = p2pc.execute(action) {
+                log::error!("could not execute action: {}", error);
+            }
         }
+        app.chats
+            .lock()
+            .unwrap()
+            .share_files(&mut p2pc, &app.keypair.get_peer_id());
 
         app.p2pc = Some(p2pc);
         app
@@ -183,50 +335,436 @@ impl App {
     fn handle_p2pc_event(
         event: p2pc_lib::Event,
         egui_ctx: &egui::Context,
-        listen_addresses: std::sync::Arc<std::sync::Mutex<Vec<Multiaddr>>>,
+        network: &NetworkState,
         chats: std::sync::Arc<std::sync::Mutex<Chats>>,
+        queued_actions: &std::sync::Mutex<Vec<p2pc_lib::Action>>,
+        local_peer_id: &str,
     ) {
         match event {
             p2pc_lib::Event::NewListenAddress(address) => {
                 log::info!("listening on {}", address);
-                let mut listen_addresses = listen_addresses.lock().unwrap();
+                let mut listen_addresses = network.listen_addresses.lock().unwrap();
                 listen_addresses.push(address);
                 egui_ctx.request_repaint();
             }
+            p2pc_lib::Event::ExpiredListenAddress(address) => {
+                log::info!("no longer listening on {}", address);
+                network
+                    .listen_addresses
+                    .lock()
+                    .unwrap()
+                    .retain(|listen_address| *listen_address != address);
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::ListenerClosed {
+                addresses, reason, ..
+            } => {
+                if let Err(error) = reason {
+                    log::warn!("listener closed: {}", error);
+                }
+                network
+                    .listen_addresses
+                    .lock()
+                    .unwrap()
+                    .retain(|listen_address| !addresses.contains(listen_address));
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::PeerConnected { peer, address } => {
+                network
+                    .connected_peers
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), address);
+                network
+                    .last_seen
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), chrono::Local::now());
+                chats.lock().unwrap().send_pending_messages_now();
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::PeerDisconnected(peer) => {
+                network
+                    .connected_peers
+                    .lock()
+                    .unwrap()
+                    .remove(&peer.to_string());
+                network.presences.lock().unwrap().remove(&peer.to_string());
+                network
+                    .last_seen
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), chrono::Local::now());
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::DialFailed { peer, error } => {
+                log::warn!("dialing {:?} failed: {}", peer, error);
+            }
+            p2pc_lib::Event::Subscribed { peer, chat_id } => {
+                log::info!("{} joined chat {}", peer, chat_id);
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&chat_id) {
+                    chat.send_pending_messages_now();
+                    // we might have missed messages of the peer while one of us was offline
+                    if chat.get_participants().contains(&peer.to_string()) {
+                        let mut queued_actions = queued_actions.lock().unwrap();
+                        queued_actions.push(p2pc_lib::Action::SyncChat {
+                            chat_id,
+                            peer,
+                            sent_after: chat.get_newest_message_time(&peer.to_string()),
+                        });
+                        // the peer might have missed changes of the members as well
+                        if !chat.get_membership_operations().is_empty() {
+                            queued_actions.push(p2pc_lib::Action::SendMembershipOperations {
+                                chat_id,
+                                operations: chat.get_membership_operations().clone(),
+                                peers: vec![peer.to_string()],
+                            });
+                        }
+                        if let Some(metadata) =
+                            chat.get_metadata(local_peer_id, vec![peer.to_string()])
+                        {
+                            queued_actions.push(p2pc_lib::Action::UpdateChatMetadata(metadata));
+                        }
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::Presence { peer, presence } => {
+                network
+                    .presences
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), presence);
+                network
+                    .last_seen
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), chrono::Local::now());
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::Typing { chat_id, peer } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat(&chat_id) {
+                    if chat.get_participants().contains(&peer.to_string()) {
+                        network
+                            .typing_peers
+                            .lock()
+                            .unwrap()
+                            .insert((chat_id, peer.to_string()), std::time::Instant::now());
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::DownloadProgress {
+                file_id,
+                received,
+                size,
+            } => {
+                let progress = if size == 0 {
+                    1.
+                } else {
+                    received as f32 / size as f32
+                };
+                network
+                    .downloads
+                    .lock()
+                    .unwrap()
+                    .insert(file_id, DownloadState::Running(progress));
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::DownloadFinished { file_id, path } => {
+                network.downloads.lock().unwrap().remove(&file_id);
+                chats.lock().unwrap().set_attachment_path(&file_id, path);
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::DownloadFailed { file_id, error } => {
+                network
+                    .downloads
+                    .lock()
+                    .unwrap()
+                    .insert(file_id, DownloadState::Failed(error.to_string()));
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::SyncRequested {
+                peer,
+                chat_id,
+                sent_after,
+            } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat(&chat_id) {
+                    if chat.get_participants().contains(&peer.to_string()) {
+                        let messages = chat.get_messages_sent_after(local_peer_id, sent_after);
+                        if !messages.is_empty() {
+                            log::info!("sending {} missed messages to {}", messages.len(), peer);
+                            queued_actions
+                                .lock()
+                                .unwrap()
+                                .push(p2pc_lib::Action::SendHistory { peer, messages });
+                            egui_ctx.request_repaint();
+                        }
+                    }
+                }
+            }
+            p2pc_lib::Event::Unsubscribed { peer, chat_id } => {
+                log::info!("{} left chat {}", peer, chat_id);
+            }
+            p2pc_lib::Event::PeerDiscovered { peer, address } => {
+                log::info!("discovered {} at {}", peer, address);
+                let address = address.with_p2p(peer).unwrap_or_else(|address| address);
+                let mut discovered_peers = network.discovered_peers.lock().unwrap();
+                if !discovered_peers.contains(&address) {
+                    discovered_peers.push(address);
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::PeerFound { peer, addresses } => {
+                network
+                    .peer_lookups
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), Some(addresses));
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::PeerNotFound(peer) => {
+                network
+                    .peer_lookups
+                    .lock()
+                    .unwrap()
+                    .insert(peer.to_string(), Some(vec![]));
+                egui_ctx.request_repaint();
+            }
             p2pc_lib::Event::ActionResult(action_result) => match action_result {
+                p2pc_lib::ActionResult::ListenOn { address, result } => {
+                    let mut listen_errors = network.listen_errors.lock().unwrap();
+                    match result {
+                        Ok(_) => listen_errors.remove(&address),
+                        Err(error) => listen_errors.insert(address, error.to_string()),
+                    };
+                    egui_ctx.request_repaint();
+                }
+                p2pc_lib::ActionResult::Dial { address, result } => {
+                    let mut dial_errors = network.dial_errors.lock().unwrap();
+                    match result {
+                        Ok(()) => dial_errors.remove(&address),
+                        Err(error) => dial_errors.insert(address, error.to_string()),
+                    };
+                    egui_ctx.request_repaint();
+                }
                 p2pc_lib::ActionResult::SendMessage {
-                    message_id: _,
-                    chat_id: _,
+                    message_id,
+                    chat_id,
                     optional_errors,
                 } => {
+                    let mut status = MessageStatus::Sent;
                     if optional_errors.is_empty() {
                         log::info!("sending message failed");
+                        status = MessageStatus::Failed;
                     } else {
                         optional_errors.iter().for_each(|optional_error| {
                             if let Some(error) = optional_error {
                                 log::error!("{}", error);
+                                match error {
+                                    libp2p::gossipsub::PublishError::Duplicate => {}
+                                    // nobody to send to right now, try again later
+                                    libp2p::gossipsub::PublishError::InsufficientPeers => {
+                                        if status == MessageStatus::Sent {
+                                            status = MessageStatus::Pending;
+                                        }
+                                    }
+                                    _ => status = MessageStatus::Failed,
+                                }
                             }
                         });
                     }
+                    if let Some(chat) = chats.lock().unwrap().get_chat_mut(&chat_id) {
+                        chat.set_message_status(&message_id, status);
+                    }
+                    egui_ctx.request_repaint();
                 }
             },
-            p2pc_lib::Event::MessageReceived(p2pc_lib::ChatMessage {
-                participants,
-                content,
-                id,
-                chat_id,
-                answer_to,
-            }) => {
-                if let Some(sender) = participants.last() {
-                    let mut chats = chats.lock().unwrap();
-                    if let Some(mut chat) = chats.remove_chat(&chat_id) {
-                        chat.insert_message(sender.clone(), content, answer_to, id);
-                        chats.add_chat(chat);
+            p2pc_lib::Event::MessageReceived {
+                message:
+                    p2pc_lib::ChatMessage {
+                        participants,
+                        content,
+                        id,
+                        chat_id,
+                        answer_to,
+                        sent_at,
+                        mut attachment,
+                    },
+                sender,
+            } => {
+                let sender = sender.to_string();
+                if let Some(attachment) = &mut attachment {
+                    if attachment.thumbnail.len() > images::MAX_THUMBNAIL_BYTES {
+                        log::info!(
+                            "ignoring oversized thumbnail of {} from {}",
+                            attachment.name,
+                            sender
+                        );
+                        attachment.thumbnail.clear();
+                    }
+                }
+                // the message is what the sender was typing
+                network
+                    .typing_peers
+                    .lock()
+                    .unwrap()
+                    .remove(&(chat_id, sender.clone()));
+                let mut chats = chats.lock().unwrap();
+                if let Some(mut chat) = chats.remove_chat(&chat_id) {
+                    if chat.get_participants().contains(&sender) {
+                        chat.insert_message(
+                            sender,
+                            content,
+                            answer_to,
+                            id,
+                            sent_at.into(),
+                            attachment,
+                        );
                     } else {
-                        let sender = sender.clone();
-                        let mut chat = Chat::new_incoming_chat(participants, chat_id);
-                        chat.insert_message(sender.clone(), content, answer_to, id);
-                        chats.add_chat(chat);
+                        log::info!("ignoring message of {} in chat {}", sender, chat_id);
+                    }
+                    chats.add_chat(chat);
+                } else if let Some(mut chat) =
+                    Chat::new_chat_from_message(participants, chat_id, &sender, local_peer_id)
+                {
+                    chat.insert_message(sender, content, answer_to, id, sent_at.into(), attachment);
+                    chats.add_chat(chat);
+                } else {
+                    log::info!("ignoring message of {} for new chat {}", sender, chat_id);
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::ChatMetadataReceived { metadata, peer } => {
+                let peer = peer.to_string();
+                // chats are only created by messages and membership operations
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&metadata.chat_id) {
+                    if metadata.icon.len() > images::MAX_THUMBNAIL_BYTES {
+                        log::info!(
+                            "ignoring oversized icon of chat {} from {}",
+                            metadata.chat_id,
+                            peer
+                        );
+                    } else if chat.get_participants().contains(&peer) {
+                        chat.apply_metadata(
+                            peer,
+                            metadata.name,
+                            metadata.description,
+                            metadata.icon,
+                            metadata.updated_at.into(),
+                        );
+                    } else {
+                        log::info!(
+                            "ignoring metadata of chat {} from {}",
+                            metadata.chat_id,
+                            peer
+                        );
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::MembershipOperationsReceived {
+                chat_id,
+                operations,
+                peer,
+            } => {
+                let mut chats = chats.lock().unwrap();
+                match chats.get_chat_mut(&chat_id) {
+                    Some(chat) => {
+                        let had_left = chat.has_left();
+                        if chat.apply_membership_operations(local_peer_id, operations) {
+                            log::info!("{} sent changes of the members of chat {}", peer, chat_id);
+                        }
+                        if chat.has_left() != had_left {
+                            queued_actions.lock().unwrap().push(match chat.has_left() {
+                                true => p2pc_lib::Action::LeaveChat(chat_id),
+                                false => p2pc_lib::Action::JoinChat(chat_id),
+                            });
+                        }
+                    }
+                    None => {
+                        // only chats we were added to are created
+                        let chat = Chat::new_chat_from_membership_operations(
+                            chat_id,
+                            local_peer_id,
+                            operations,
+                        );
+                        // the operations have to come from a member, not from anyone who knows them
+                        if !chat.has_left() && chat.get_participants().contains(&peer.to_string()) {
+                            log::info!("{} added us to chat {}", peer, chat_id);
+                            queued_actions
+                                .lock()
+                                .unwrap()
+                                .push(p2pc_lib::Action::JoinChat(chat_id));
+                            chats.add_chat(chat);
+                        }
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::MessageEdited { edit, peer } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&edit.chat_id) {
+                    if !chat.get_participants().contains(&peer.to_string())
+                        || !chat.apply_edit(
+                            &edit.message_id,
+                            &peer.to_string(),
+                            edit.content,
+                            edit.edited_at.into(),
+                        )
+                    {
+                        log::info!("ignoring edit of message {} by {}", edit.message_id, peer);
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::ReactionReceived { reaction, peer } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&reaction.chat_id) {
+                    if chat.get_participants().contains(&peer.to_string())
+                        && reaction.emoji.chars().count() <= MAX_REACTION_CHARS
+                    {
+                        chat.apply_reaction(
+                            &reaction.message_id,
+                            peer.to_string(),
+                            reaction.emoji,
+                            reaction.added,
+                            reaction.reacted_at.into(),
+                        );
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::MessageRetracted {
+                message_id,
+                chat_id,
+                peer,
+            } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&chat_id) {
+                    if !chat.apply_retraction(&message_id, &peer.to_string()) {
+                        log::info!("ignoring retraction of message {} by {}", message_id, peer);
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::MessageDelivered {
+                message_id,
+                chat_id,
+                peer,
+            } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&chat_id) {
+                    if chat.get_participants().contains(&peer.to_string()) {
+                        chat.mark_delivered(&message_id, peer.to_string());
+                    }
+                }
+                egui_ctx.request_repaint();
+            }
+            p2pc_lib::Event::MessageRead {
+                message_id,
+                chat_id,
+                peer,
+            } => {
+                if let Some(chat) = chats.lock().unwrap().get_chat_mut(&chat_id) {
+                    if chat.get_participants().contains(&peer.to_string()) {
+                        chat.mark_read(&message_id, peer.to_string());
                     }
                 }
                 egui_ctx.request_repaint();
@@ -302,7 +840,21 @@ impl eframe::App for App {
             });
         });
 
-        if let Some(chat_id) = self.current_chat_id {
+        let has_left_current_chat = self.current_chat_id.is_some_and(|chat_id| {
+            self.chats
+                .lock()
+                .unwrap()
+                .get_chat(&chat_id)
+                .is_some_and(Chat::has_left)
+        });
+        if has_left_current_chat {
+            egui::TopBottomPanel::bottom("left_chat")
+                .show_separator_line(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(RichText::new("You are no longer a member of this chat.").italics());
+                });
+        } else if let Some(chat_id) = self.current_chat_id {
             egui::TopBottomPanel::bottom("Send Chat Message")
                 .show_separator_line(false)
                 .resizable(false)
@@ -311,8 +863,24 @@ impl eframe::App for App {
                         ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
                             let button_response = ui.add_enabled(
                                 !self.current_message.trim().is_empty(),
-                                Button::new("Send ➡").min_size(vec2(0., ui.available_height())),
+                                Button::new(if self.current_message_edit_of.is_some() {
+                                    "Save ✏"
+                                } else {
+                                    "Send ➡"
+                                })
+                                .min_size(vec2(0., ui.available_height())),
                             );
+                            if ui
+                                .selectable_label(self.current_file_path.is_some(), "📎")
+                                .on_hover_text("Send a file, or drop it onto the window")
+                                .clicked()
+                            {
+                                self.current_file_path = match self.current_file_path {
+                                    Some(_) => None,
+                                    None => Some(String::new()),
+                                };
+                                self.send_file_error = None;
+                            }
 
                             let textedit_response = ui.add(
                                 egui::TextEdit::singleline(&mut self.current_message)
@@ -323,6 +891,20 @@ impl eframe::App for App {
                                     )
                                     .min_size(ui.available_size()),
                             );
+                            if textedit_response.changed()
+                                && !self.current_message.trim().is_empty()
+                            {
+                                if let (Some(p2pc), Some(chat)) = (
+                                    &mut self.p2pc,
+                                    self.chats.lock().unwrap().get_chat(&chat_id),
+                                ) {
+                                    p2pc.execute(p2pc_lib::Action::SendTyping {
+                                        chat_id,
+                                        participants: chat.get_participants().clone(),
+                                    })
+                                    .ok();
+                                }
+                            }
 
                             // send message
                             if button_response.clicked()
@@ -330,13 +912,26 @@ impl eframe::App for App {
                                     && ui.input(|i| i.key_pressed(egui::Key::Enter)))
                             {
                                 if let Some(p2pc) = &mut self.p2pc {
-                                    self.chats.lock().unwrap().send_message(
-                                        &chat_id,
-                                        p2pc,
-                                        own_public_key_base_64.clone(),
-                                        self.current_message.trim().to_string(),
-                                        self.current_message_answer_to,
-                                    );
+                                    match self.current_message_edit_of.take() {
+                                        Some(message_id) => {
+                                            if let Some(chat) =
+                                                self.chats.lock().unwrap().get_chat_mut(&chat_id)
+                                            {
+                                                chat.edit_message(
+                                                    p2pc,
+                                                    &message_id,
+                                                    self.current_message.trim().to_string(),
+                                                );
+                                            }
+                                        }
+                                        None => self.chats.lock().unwrap().send_message(
+                                            &chat_id,
+                                            p2pc,
+                                            own_public_key_base_64.clone(),
+                                            self.current_message.trim().to_string(),
+                                            self.current_message_answer_to,
+                                        ),
+                                    }
                                     self.current_message.clear();
                                     self.current_message_answer_to = None;
                                 }
@@ -346,6 +941,64 @@ impl eframe::App for App {
                         });
                     });
                 });
+
+            // a typing peer sends signals in intervals, so it stopped if we missed more than one
+            let typing_timeout = p2pc_lib::TYPING_SIGNAL_INTERVAL * 2;
+            let mut typing_peers = self.network.typing_peers.lock().unwrap();
+            typing_peers.retain(|_, last_signal| last_signal.elapsed() < typing_timeout);
+            let mut names = typing_peers
+                .iter()
+                .filter(|((typing_chat_id, _), _)| *typing_chat_id == chat_id)
+                .map(|((_, peer), _)| peer_name(&self.contacts, peer))
+                .collect::<Vec<_>>();
+            if let Some(last_signal) = typing_peers.values().min() {
+                ctx.request_repaint_after(typing_timeout.saturating_sub(last_signal.elapsed()));
+            }
+            drop(typing_peers);
+            names.sort();
+            if let Some(last) = names.pop() {
+                let text = if names.is_empty() {
+                    format!("{} is typing…", last)
+                } else {
+                    format!("{} and {} are typing…", names.join(", "), last)
+                };
+                egui::TopBottomPanel::bottom("typing_indicator")
+                    .show_separator_line(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.label(
+                            RichText::new(text)
+                                .italics()
+                                .color(egui::Color32::GRAY)
+                                .size(10.),
+                        );
+                    });
+            }
+            if let Some(edit_of) = self.current_message_edit_of {
+                egui::TopBottomPanel::bottom("edit_of_message")
+                    .show_separator_line(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
+                            if ui.button("❌").clicked() {
+                                self.current_message_edit_of = None;
+                                self.current_message.clear();
+                            }
+                            if let Some(current_chat) =
+                                self.chats.lock().unwrap().get_chat(&chat_id)
+                            {
+                                if let Some(edit_of_message) =
+                                    current_chat.get_message_from_id(&edit_of)
+                                {
+                                    ui.add(
+                                        Label::new(format!("{} ✏", edit_of_message.get_content()))
+                                            .truncate(true),
+                                    );
+                                }
+                            }
+                        });
+                    });
+            }
             if let Some(answer_to) = self.current_message_answer_to {
                 egui::TopBottomPanel::bottom("answer_to_message")
                     .show_separator_line(false)
@@ -364,7 +1017,7 @@ impl eframe::App for App {
                                     ui.add(
                                         Label::new(format!(
                                             "{} ⮪",
-                                            answer_to_message.get_content()
+                                            message_text(answer_to_message)
                                         ))
                                         .truncate(true),
                                     );
@@ -373,6 +1026,86 @@ impl eframe::App for App {
                         });
                     });
             }
+
+            let dropped_files: Vec<std::path::PathBuf> = ctx.input(|i| {
+                i.raw
+                    .dropped_files
+                    .iter()
+                    .filter_map(|dropped_file| dropped_file.path.clone())
+                    .collect()
+            });
+            let mut files_to_send = dropped_files;
+            let mut close_file_panel = false;
+            if let Some(current_file_path) = &mut self.current_file_path {
+                egui::TopBottomPanel::bottom("send_file")
+                    .show_separator_line(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
+                            if ui.button("❌").clicked() {
+                                close_file_panel = true;
+                            }
+                            if ui
+                                .add_enabled(
+                                    !current_file_path.trim().is_empty(),
+                                    Button::new("Send File ➡"),
+                                )
+                                .clicked()
+                            {
+                                files_to_send.push(current_file_path.trim().into());
+                            }
+                            if let Some(error) = &self.send_file_error {
+                                ui.label(RichText::new("⚠").color(egui::Color32::RED))
+                                    .on_hover_text(error);
+                            }
+                            ui.add(
+                                TextEdit::singleline(current_file_path)
+                                    .hint_text("Path of the file to send")
+                                    .desired_width(ui.available_width()),
+                            );
+                        });
+                    });
+            }
+            if close_file_panel {
+                self.current_file_path = None;
+                self.send_file_error = None;
+            }
+            // hashing large files takes a while, the message is sent once it is done
+            for path in files_to_send {
+                self.current_file_path = None;
+                self.send_file_error = None;
+                let prepared_files = self.prepared_files.clone();
+                let ctx = ctx.clone();
+                tokio::task::spawn_blocking(move || {
+                    let attachment = Chat::prepare_attachment(&path);
+                    prepared_files
+                        .lock()
+                        .unwrap()
+                        .push((chat_id, path, attachment));
+                    ctx.request_repaint();
+                });
+            }
+        }
+
+        let prepared_files = std::mem::take(&mut *self.prepared_files.lock().unwrap());
+        if let Some(p2pc) = &mut self.p2pc {
+            for (chat_id, path, attachment) in prepared_files {
+                let result = attachment.and_then(|attachment| {
+                    self.chats.lock().unwrap().send_file(
+                        &chat_id,
+                        p2pc,
+                        own_public_key_base_64.clone(),
+                        &path,
+                        attachment,
+                    )
+                });
+                if let Err(error) = result {
+                    log::warn!("could not send {:?}: {}", path, error);
+                    self.send_file_error = Some(format!("{}: {}", path.display(), error));
+                    self.current_file_path
+                        .get_or_insert_with(|| path.display().to_string());
+                }
+            }
         }
 
         egui::SidePanel::left("chats")
@@ -396,6 +1129,16 @@ impl eframe::App for App {
                             self.chats.lock().unwrap().get_chats().iter().for_each(
                                 |(&chat_id, chat)| {
                                     ui.horizontal(|ui| {
+                                        if let Some(icon) = self.images.get_icon(
+                                            ui.ctx(),
+                                            &chat_id,
+                                            chat.get_icon(),
+                                        ) {
+                                            ui.add(
+                                                egui::Image::new(&icon)
+                                                    .fit_to_exact_size(vec2(16., 16.)),
+                                            );
+                                        }
                                         if ui
                                             .add(egui::SelectableLabel::new(
                                                 self.current_chat_id == Some(chat_id),
@@ -403,6 +1146,10 @@ impl eframe::App for App {
                                             ))
                                             .clicked()
                                         {
+                                            if self.current_chat_id != Some(chat_id) {
+                                                // the edited message belongs to the previous chat
+                                                self.current_message_edit_of = None;
+                                            }
                                             self.current_chat_id = Some(chat_id);
                                         }
                                     });
@@ -418,30 +1165,52 @@ impl eframe::App for App {
                                 },
                             );
                         });
-                    match self.edit_chat_mode {
-                        EditMode::Delete(selected_chat_id) => {
-                            if let Some(current_chat_id) = self.current_chat_id {
-                                if selected_chat_id == current_chat_id {
-                                    self.current_chat_id = None;
+                    if let EditMode::Delete(selected_chat_id) = self.edit_chat_mode {
+                        if let Some(current_chat_id) = self.current_chat_id {
+                            if selected_chat_id == current_chat_id {
+                                self.current_chat_id = None;
+                            }
+                        }
+                        let chat = self.chats.lock().unwrap().remove_chat(&selected_chat_id);
+                        if let Some(p2pc) = &mut self.p2pc {
+                            // tell the other members before we stop listening to the chat
+                            if let Some(mut chat) = chat.filter(|chat| !chat.has_left()) {
+                                let leave =
+                                    vec![(self.keypair.get_peer_id(), MembershipChange::Remove)];
+                                if let Err(error) =
+                                    chat.change_members(p2pc, &self.keypair.get_keypair(), leave)
+                                {
+                                    log::error!(
+                                        "could not leave chat {}: {}",
+                                        selected_chat_id,
+                                        error
+                                    );
                                 }
                             }
-                            self.chats.lock().unwrap().remove_chat(&selected_chat_id);
-                            self.edit_chat_mode = EditMode::None;
+                            p2pc.execute(p2pc_lib::Action::LeaveChat(selected_chat_id))
+                                .ok();
                         }
-                        _ => {}
+                        self.edit_chat_mode = EditMode::None;
                     }
                 });
             });
 
+        // members of a chat we left cannot be changed by us anymore
+        let can_change_members = match self.edit_chat_mode {
+            EditMode::New => true,
+            EditMode::Edit(chat_id) => self
+                .chats
+                .lock()
+                .unwrap()
+                .get_chat(&chat_id)
+                .is_some_and(|chat| !chat.has_left()),
+            _ => false,
+        };
         egui::SidePanel::left("edit_chats")
             .min_width(60.)
             .show_animated(
                 ctx,
-                match self.edit_chat_mode {
-                    EditMode::New => true,
-                    EditMode::Edit(_) => true,
-                    _ => false,
-                },
+                matches!(self.edit_chat_mode, EditMode::New | EditMode::Edit(_)),
                 |ui| {
                     let title = match self.edit_chat_mode {
                         EditMode::New => "New Chat".to_string(),
@@ -472,20 +1241,57 @@ impl eframe::App for App {
                                     .min_size(vec2(50., 0.)),
                             );
                             ui.end_row();
+                            ui.label("Description:");
+                            ui.add(
+                                egui::TextEdit::multiline(&mut self.chat_edit_window_content.description)
+                                    .desired_rows(2)
+                                    .min_size(vec2(50., 0.)),
+                            );
+                            ui.end_row();
+                            ui.label("Icon:");
+                            ui.horizontal(|ui| {
+                                match self.images.get_icon(ui.ctx(), &Uuid::nil(), &self.chat_edit_window_content.icon) {
+                                    Some(icon) => {
+                                        ui.add(egui::Image::new(&icon).fit_to_exact_size(vec2(24., 24.)));
+                                        if ui.button("🗑").on_hover_text("Remove icon").clicked() {
+                                            self.chat_edit_window_content.icon.clear();
+                                        }
+                                    }
+                                    None => {
+                                        let icon_path = self.chat_edit_window_content.icon_path.trim().to_string();
+                                        if ui.add_enabled(!icon_path.is_empty(), egui::Button::new("➕")).clicked() {
+                                            match images::create_icon(std::path::Path::new(&icon_path)) {
+                                                Some(icon) => {
+                                                    self.chat_edit_window_content.icon = icon;
+                                                    self.chat_edit_window_content.icon_path.clear();
+                                                    self.chat_edit_window_content.icon_error = None;
+                                                }
+                                                None => {
+                                                    self.chat_edit_window_content.icon_error =
+                                                        Some(format!("{} is not an image", icon_path));
+                                                }
+                                            }
+                                        }
+                                        if let Some(error) = &self.chat_edit_window_content.icon_error {
+                                            ui.label(RichText::new("⚠").color(egui::Color32::RED)).on_hover_text(error);
+                                        }
+                                        ui.add(
+                                            egui::TextEdit::singleline(&mut self.chat_edit_window_content.icon_path)
+                                                .hint_text("Path of an image")
+                                                .min_size(vec2(50., 0.)),
+                                        );
+                                    }
+                                }
+                            });
+                            ui.end_row();
                         });
                     ui.separator();
                     ui.heading("Participants");
-                    match self.edit_chat_mode {
-                        EditMode::New => {
-                            self.show_contacts = true;
-                        }
-                        _ => {}
+                    if can_change_members {
+                        self.show_contacts = true;
                     }
                     egui::Grid::new("chat_edit_participants_grid")
-                        .num_columns(match self.edit_chat_mode {
-                            EditMode::New => 2,
-                            _ => 1,
-                        })
+                        .num_columns(if can_change_members { 2 } else { 1 })
                         .min_col_width(0.)
                         .striped(false)
                         .show(ui, |ui| {
@@ -511,22 +1317,16 @@ impl eframe::App for App {
                                             ));
                                         }
                                     });
-                                    match self.edit_chat_mode {
-                                        EditMode::New => {
-                                            if ui.button("🗑").clicked() {
-                                                chat_edit_mode_participant_edit_mode =
-                                                    EditMode::Delete(index);
-                                            }
-                                        }
-                                        _ => {}
+                                    if can_change_members
+                                        && ui.button("🗑").clicked()
+                                    {
+                                        chat_edit_mode_participant_edit_mode =
+                                            EditMode::Delete(index);
                                     }
                                     ui.end_row();
                                 });
-                            match chat_edit_mode_participant_edit_mode {
-                                EditMode::Delete(idx) => {
-                                    self.chat_edit_window_content.participants.remove(idx);
-                                }
-                                _ => {}
+                            if let EditMode::Delete(idx) = chat_edit_mode_participant_edit_mode {
+                                self.chat_edit_window_content.participants.remove(idx);
                             }
                         });
                     ui.separator();
@@ -553,11 +1353,59 @@ impl eframe::App for App {
                                     if self.current_chat_id.is_none() {
                                         self.current_chat_id = Some(*chat.get_chat_id());
                                     }
+                                    if let Some(p2pc) = &mut self.p2pc {
+                                        p2pc.execute(p2pc_lib::Action::JoinChat(
+                                            *chat.get_chat_id(),
+                                        ))
+                                        .ok();
+                                        chat.set_metadata(
+                                            p2pc,
+                                            &self.keypair.get_peer_id(),
+                                            self.chat_edit_window_content.name.clone(),
+                                            self.chat_edit_window_content.description.clone(),
+                                            self.chat_edit_window_content.icon.clone(),
+                                        );
+                                    }
                                     self_chats.add_chat(chat);
                                 }
                                 EditMode::Edit(chat_id) => {
                                     if let Some(mut chat) = self_chats.remove_chat(&chat_id) {
-                                        chat.name = self.chat_edit_window_content.name.clone();
+                                        let content = &self.chat_edit_window_content;
+                                        let metadata_changed = content.name != chat.name
+                                            || content.description != chat.description
+                                            || content.icon != chat.get_icon();
+                                        let participants = &content.participants;
+                                        let changes: Vec<(String, MembershipChange)> = participants
+                                            .iter()
+                                            .filter(|participant| !chat.get_participants().contains(participant))
+                                            .map(|participant| (participant.clone(), MembershipChange::Add))
+                                            .chain(
+                                                chat.get_participants()
+                                                    .iter()
+                                                    .filter(|participant| !participants.contains(participant))
+                                                    .map(|participant| (participant.clone(), MembershipChange::Remove)),
+                                            )
+                                            .collect();
+                                        // added members only learn the name of the chat with its next change
+                                        let members_added = changes.iter().any(|(_, change)| *change == MembershipChange::Add);
+                                        if let Some(p2pc) = &mut self.p2pc {
+                                            if !changes.is_empty() {
+                                                if let Err(error) =
+                                                    chat.change_members(p2pc, &self.keypair.get_keypair(), changes)
+                                                {
+                                                    log::error!("could not change the members of chat {}: {}", chat_id, error);
+                                                }
+                                            }
+                                            if metadata_changed || members_added {
+                                                chat.set_metadata(
+                                                    p2pc,
+                                                    &self.keypair.get_peer_id(),
+                                                    content.name.clone(),
+                                                    content.description.clone(),
+                                                    content.icon.clone(),
+                                                );
+                                            }
+                                        }
                                         self_chats.add_chat(chat);
                                     }
                                     self.edit_chat_mode = EditMode::None;
@@ -565,6 +1413,26 @@ impl eframe::App for App {
                                 _ => {}
                             }
                         }
+                        if let EditMode::Edit(chat_id) = self.edit_chat_mode {
+                            if ui
+                                .add_enabled(can_change_members, egui::Button::new("🚪 Leave chat"))
+                                .clicked()
+                            {
+                                if let (Some(chat), Some(p2pc)) =
+                                    (self.chats.lock().unwrap().get_chat_mut(&chat_id), &mut self.p2pc)
+                                {
+                                    let leave = vec![(self.keypair.get_peer_id(), MembershipChange::Remove)];
+                                    match chat.change_members(p2pc, &self.keypair.get_keypair(), leave) {
+                                        Ok(()) => {
+                                            p2pc.execute(p2pc_lib::Action::LeaveChat(chat_id)).ok();
+                                        }
+                                        Err(error) => log::error!("could not leave chat {}: {}", chat_id, error),
+                                    }
+                                }
+                                self.edit_chat_mode = EditMode::None;
+                                self.show_contacts = false;
+                            }
+                        }
                     });
                 },
             );
@@ -584,35 +1452,83 @@ impl eframe::App for App {
                 });
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     egui::Grid::new("conacts_grid")
-                        .num_columns(match self.edit_chat_mode {
-                            EditMode::New => 3,
-                            _ => 2,
-                        })
+                        .num_columns(if can_change_members { 3 } else { 2 })
                         .striped(false)
                         .min_col_width(0.)
                         .show(ui, |ui| {
                             self.contacts.get_contacts().iter().for_each(
                                 |(public_key, contact)| {
                                     ui.horizontal(|ui| {
-                                        match self.edit_chat_mode {
-                                            EditMode::New => {
-                                                if ui.button("➕").clicked()
-                                                    && !self
-                                                        .chat_edit_window_content
-                                                        .participants
-                                                        .contains(&contact.public_key)
-                                                {
-                                                    self.chat_edit_window_content
-                                                        .participants
-                                                        .push(contact.public_key.clone());
-                                                }
-                                            }
-                                            _ => {}
+                                        if can_change_members
+                                            && ui.button("➕").clicked()
+                                            && !self
+                                                .chat_edit_window_content
+                                                .participants
+                                                .contains(&contact.public_key)
+                                        {
+                                            self.chat_edit_window_content
+                                                .participants
+                                                .push(contact.public_key.clone());
                                         }
+                                        let (presence_color, presence_text) = presence_status(
+                                            &self.network,
+                                            public_key,
+                                            contact.last_seen.as_ref(),
+                                        );
+                                        let connection_text = match self
+                                            .network
+                                            .connected_peers
+                                            .lock()
+                                            .unwrap()
+                                            .get(public_key)
+                                        {
+                                            Some(address) => format!("Connected via {}", address),
+                                            None => "Not connected".to_string(),
+                                        };
+                                        ui.label(RichText::new("●").color(presence_color))
+                                            .on_hover_text(format!(
+                                                "{}\n{}",
+                                                presence_text, connection_text
+                                            ));
                                         ui.add(Label::new(
                                             RichText::new(contact.name.clone())
                                                 .color(contact.color),
                                         ));
+                                        let lookup_status = match self
+                                            .network
+                                            .peer_lookups
+                                            .lock()
+                                            .unwrap()
+                                            .get(public_key)
+                                        {
+                                            None => "Find contact in the network".to_string(),
+                                            Some(None) => "Searching...".to_string(),
+                                            Some(Some(addresses)) if addresses.is_empty() => {
+                                                "Not found".to_string()
+                                            }
+                                            Some(Some(addresses)) => addresses
+                                                .iter()
+                                                .map(|address| address.to_string())
+                                                .collect::<Vec<_>>()
+                                                .join("\n"),
+                                        };
+                                        if ui
+                                            .small_button("🔍")
+                                            .on_hover_text(lookup_status)
+                                            .clicked()
+                                        {
+                                            if let (Some(p2pc), Ok(peer)) = (
+                                                &mut self.p2pc,
+                                                libp2p::PeerId::from_str(public_key),
+                                            ) {
+                                                self.network
+                                                    .peer_lookups
+                                                    .lock()
+                                                    .unwrap()
+                                                    .insert(public_key.clone(), None);
+                                                p2pc.execute(p2pc_lib::Action::FindPeer(peer)).ok();
+                                            }
+                                        }
                                     });
                                     if ui.button("✏").clicked() {
                                         self.edit_contact_mode = EditMode::Edit(public_key.clone());
@@ -626,12 +1542,11 @@ impl eframe::App for App {
                                     ui.end_row();
                                 },
                             );
-                            match self.edit_contact_mode.clone() {
-                                EditMode::Delete(delete_public_key) => {
-                                    self.contacts.remove_contact(&delete_public_key);
-                                    self.edit_contact_mode = EditMode::None;
-                                }
-                                _ => {}
+                            if let EditMode::Delete(delete_public_key) =
+                                self.edit_contact_mode.clone()
+                            {
+                                self.contacts.remove_contact(&delete_public_key);
+                                self.edit_contact_mode = EditMode::None;
                             }
                         });
                 });
@@ -641,11 +1556,7 @@ impl eframe::App for App {
             .min_width(60.)
             .show_animated(
                 ctx,
-                match self.edit_contact_mode {
-                    EditMode::New => true,
-                    EditMode::Edit(_) => true,
-                    _ => false,
-                },
+                matches!(self.edit_contact_mode, EditMode::New | EditMode::Edit(_)),
                 |ui| {
                     let title = match self.edit_contact_mode.clone() {
                         EditMode::New => "New Contact".to_string(),
@@ -707,10 +1618,18 @@ impl eframe::App for App {
                                     self.edit_contact_mode = EditMode::None;
                                 }
                                 EditMode::Edit(public_key) => {
+                                    let last_seen = self
+                                        .contacts
+                                        .get_contact(&public_key)
+                                        .and_then(|contact| contact.last_seen);
                                     self.contacts.remove_contact(&public_key);
-                                    self.contacts.add_contact(Contact::from_contact_window(
+                                    let mut contact = Contact::from_contact_window(
                                         &self.contact_edit_window_content,
-                                    ));
+                                    );
+                                    if contact.public_key == public_key {
+                                        contact.last_seen = last_seen;
+                                    }
+                                    self.contacts.add_contact(contact);
 
                                     self.edit_contact_mode = EditMode::None;
                                 }
@@ -727,7 +1646,7 @@ impl eframe::App for App {
             ScrollArea::vertical().show(ui, |ui| {
                 ui.collapsing("Peers", |ui| {
                     Grid::new("peer_list_grid")
-                        .num_columns(2)
+                        .num_columns(3)
                         .min_col_width(0.)
                         .show(ui, |ui| {
                             let mut peer_list_entry_edit_mode: EditMode<usize> = EditMode::None;
@@ -736,6 +1655,10 @@ impl eframe::App for App {
                                     peer_list_entry_edit_mode = EditMode::Delete(peer_index);
                                 }
                                 ui.add(Label::new(peer.to_string()).truncate(true));
+                                if let Some(error) = self.network.dial_errors.lock().unwrap().get(peer) {
+                                    ui.label(RichText::new("⚠").color(egui::Color32::RED))
+                                        .on_hover_text(error);
+                                }
                                 ui.end_row();
                             }
                             if let EditMode::Delete(peer_index) = peer_list_entry_edit_mode {
@@ -770,29 +1693,267 @@ impl eframe::App for App {
                             .changed()
                         {
                             self.settings.current_peer_is_valid =
-                                match Multiaddr::from_str(&self.settings.current_peer) {
-                                    Ok(_) => true,
-                                    Err(_) => false,
-                                } && !self.settings.current_peer.is_empty();
+                                Multiaddr::from_str(&self.settings.current_peer).is_ok()
+                                    && !self.settings.current_peer.is_empty();
                         }
                     });
                 });
 
+                ui.collapsing("Connected Peers", |ui| {
+                    Grid::new("connected_peers_grid")
+                        .num_columns(2)
+                        .min_col_width(0.)
+                        .show(ui, |ui| {
+                            for (peer, address) in self.network.connected_peers.lock().unwrap().iter() {
+                                let name = self
+                                    .contacts
+                                    .get_contact(peer)
+                                    .map(|contact| contact.name.clone())
+                                    .unwrap_or_else(|| peer.clone());
+                                ui.add(Label::new(name).truncate(true))
+                                    .on_hover_text(peer);
+                                ui.add(Label::new(address.to_string()).truncate(true));
+                                ui.end_row();
+                            }
+                        });
+                });
+
                 ui.collapsing("Listen Addresses", |ui| {
-                    for listen_address in self.listen_addresses.lock().unwrap().iter() {
+                    for listen_address in self.network.listen_addresses.lock().unwrap().iter() {
                         ui.label(listen_address.to_string());
                         ui.separator();
                     }
+                    for (address, error) in self.network.listen_errors.lock().unwrap().iter() {
+                        ui.label(RichText::new(address.to_string()).color(egui::Color32::RED))
+                            .on_hover_text(error);
+                        ui.separator();
+                    }
+                });
+
+                ui.collapsing("Relays", |ui| {
+                    ui.checkbox(&mut self.settings.relay_server, "Relay for other peers")
+                        .on_hover_text(
+                            "Only useful if this device is publicly reachable. Takes effect after restarting p2pc.",
+                        );
+                    Grid::new("relay_list_grid")
+                        .num_columns(3)
+                        .min_col_width(0.)
+                        .show(ui, |ui| {
+                            let mut relay_list_entry_edit_mode: EditMode<usize> = EditMode::None;
+                            for (relay_index, relay) in self.settings.relays.iter().enumerate() {
+                                if ui
+                                    .button("🗑")
+                                    .on_hover_text("Remove relay (takes effect after restarting p2pc)")
+                                    .clicked()
+                                {
+                                    relay_list_entry_edit_mode = EditMode::Delete(relay_index);
+                                }
+                                ui.add(Label::new(relay.to_string()).truncate(true));
+                                if let Some(error) = self
+                                    .network
+                                    .listen_errors
+                                    .lock()
+                                    .unwrap()
+                                    .get(&relay.clone().with(Protocol::P2pCircuit))
+                                {
+                                    ui.label(RichText::new("⚠").color(egui::Color32::RED))
+                                        .on_hover_text(error);
+                                }
+                                ui.end_row();
+                            }
+                            if let EditMode::Delete(relay_index) = relay_list_entry_edit_mode {
+                                self.settings.relays.remove(relay_index);
+                            }
+                        });
+                    ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
+                        if ui
+                            .add_enabled(
+                                self.settings.current_relay_is_valid,
+                                Button::new("➕ Add Relay"),
+                            )
+                            .on_disabled_hover_text(
+                                "Please enter a valid Multi Address ending with /p2p/<peer id>.",
+                            )
+                            .clicked()
+                        {
+                            match Multiaddr::from_str(&self.settings.current_relay) {
+                                Ok(relay) => {
+                                    self.settings.relays.push(relay.clone());
+                                    self.settings.current_relay.clear();
+                                    self.settings.current_relay_is_valid = false;
+                                    if let Some(p2pc) = &mut self.p2pc {
+                                        p2pc.execute(p2pc_lib::Action::ListenOn(
+                                            relay.with(Protocol::P2pCircuit),
+                                        ))
+                                        .ok();
+                                    }
+                                }
+                                Err(_) => {
+                                    log::warn!("Invalid relay address");
+                                }
+                            }
+                        }
+                        if ui
+                            .add(TextEdit::singleline(&mut self.settings.current_relay))
+                            .changed()
+                        {
+                            // reservations need the peer id of the relay
+                            self.settings.current_relay_is_valid =
+                                Multiaddr::from_str(&self.settings.current_relay).is_ok_and(
+                                    |relay| matches!(relay.iter().last(), Some(Protocol::P2p(_))),
+                                );
+                        }
+                    });
+                });
+
+                ui.collapsing("Downloads", |ui| {
+                    ui.label("Save attachments to");
+                    ui.add(
+                        TextEdit::singleline(&mut self.settings.download_directory)
+                            .hint_text(default_download_directory().display().to_string()),
+                    );
+                });
+
+                ui.collapsing("Local Discovery", |ui| {
+                    ui.checkbox(&mut self.settings.mdns, "Discover peers via mDNS")
+                        .on_hover_text("Takes effect after restarting p2pc.");
+                    Grid::new("discovered_peers_grid")
+                        .num_columns(2)
+                        .min_col_width(0.)
+                        .show(ui, |ui| {
+                            for peer in self.network.discovered_peers.lock().unwrap().iter() {
+                                if ui
+                                    .add_enabled(
+                                        !self.settings.peers.contains(peer),
+                                        Button::new("➕"),
+                                    )
+                                    .on_hover_text("Save peer")
+                                    .clicked()
+                                {
+                                    self.settings.peers.push(peer.clone());
+                                }
+                                ui.add(Label::new(peer.to_string()).truncate(true));
+                                ui.end_row();
+                            }
+                        });
                 });
             });
         });
+        // the open chat counts as read while the window has focus
+        if let (Some(chat_id), Some(p2pc)) = (self.current_chat_id, &mut self.p2pc) {
+            if ctx.input(|i| i.focused) {
+                if let Some(chat) = self.chats.lock().unwrap().get_chat_mut(&chat_id) {
+                    chat.send_read_receipt(p2pc);
+                }
+            }
+        }
+
+        if let Some(p2pc) = &mut self.p2pc {
+            for action in self.queued_actions.lock().unwrap().drain(..) {
+                if let Err(error) = p2pc.execute(action) {
+                    log::error!("could not execute action: {}", error);
+                }
+            }
+        }
+
+        // tell our contacts whether we are at the device
+        if ctx.input(|i| !i.events.is_empty() || i.pointer.is_moving()) {
+            self.last_input = std::time::Instant::now();
+        }
+        let presence = match AWAY_TIMEOUT.checked_sub(self.last_input.elapsed()) {
+            Some(remaining) => {
+                ctx.request_repaint_after(remaining);
+                p2pc_lib::Presence::Online
+            }
+            None => p2pc_lib::Presence::Away,
+        };
+        let mut presence_peers = self
+            .contacts
+            .get_contacts()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        presence_peers.sort();
+        let announcement = (presence, presence_peers);
+        if self.announced_presence.as_ref() != Some(&announcement) {
+            if let Some(p2pc) = &mut self.p2pc {
+                p2pc.execute(p2pc_lib::Action::SetPresence {
+                    presence: announcement.0,
+                    peers: announcement.1.clone(),
+                })
+                .ok();
+                self.announced_presence = Some(announcement);
+            }
+        }
+        for (peer, last_seen) in self.network.last_seen.lock().unwrap().drain() {
+            self.contacts.set_last_seen(&peer, last_seen);
+        }
+
+        // resend messages that could not be sent yet, e.g. while offline
+        if let Some(p2pc) = &mut self.p2pc {
+            if let Some(next_send_attempt) = self.chats.lock().unwrap().send_pending_messages(p2pc)
+            {
+                ctx.request_repaint_after(
+                    next_send_attempt.saturating_duration_since(std::time::Instant::now()),
+                );
+            }
+        }
+
+        let mut retry_message_id = None;
+        let mut delete_message = None;
+        let mut toggle_reaction = None;
+        let mut download_message_id = None;
         egui::CentralPanel::default().show(ctx, |ui| {
             match self.current_chat_id {
                 Some(current_chat_id) => match self.chats.lock().unwrap().get_chat(&current_chat_id) {
                     Some(current_chat) => {
                         ui.horizontal(|ui| {
                             ui.heading(RichText::new("Chat:"));
+                            if let Some(icon) = self.images.get_icon(ctx, &current_chat_id, current_chat.get_icon()) {
+                                ui.add(egui::Image::new(&icon).fit_to_exact_size(vec2(24., 24.)));
+                            }
                             ui.heading(current_chat.name.clone());
+                            if !current_chat.description.is_empty() {
+                                ui.label(RichText::new(current_chat.description.clone()).italics().size(10.));
+                            }
+                            let participant_statuses = current_chat
+                                .get_participants()
+                                .iter()
+                                .map(|participant| {
+                                    let contact = self.contacts.get_contact(participant);
+                                    let (color, text) = presence_status(
+                                        &self.network,
+                                        participant,
+                                        contact.and_then(|contact| contact.last_seen.as_ref()),
+                                    );
+                                    let name = contact.map_or(participant.clone(), |contact| contact.name.clone());
+                                    (color, name, text)
+                                })
+                                .collect::<Vec<_>>();
+                            match participant_statuses.as_slice() {
+                                [(color, _, text)] => {
+                                    ui.label(RichText::new(text).color(*color).size(10.));
+                                }
+                                statuses => {
+                                    let online = current_chat
+                                        .get_participants()
+                                        .iter()
+                                        .filter(|participant| self.network.get_presence(participant).is_some())
+                                        .count();
+                                    ui.label(
+                                        RichText::new(format!("{} of {} online", online, statuses.len()))
+                                            .color(egui::Color32::GRAY)
+                                            .size(10.),
+                                    )
+                                    .on_hover_text(
+                                        statuses
+                                            .iter()
+                                            .map(|(_, name, text)| format!("{}: {}", name, text))
+                                            .collect::<Vec<_>>()
+                                            .join("\n"),
+                                    );
+                                }
+                            }
                             ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
                                 ui.checkbox(&mut self.auto_scroll, "Autoscroll");
                             });
@@ -805,7 +1966,13 @@ impl eframe::App for App {
                                     .min_col_width(ui.available_width())
                                     .striped(false)
                                     .show(ui, |ui| {
-                                        for message in current_chat.get_chat_messages() {
+                                        let last_read_indices = current_chat.get_last_read_indices();
+                                        for (message_index, message) in current_chat
+                                            .get_chat_messages()
+                                            .iter()
+                                            .enumerate()
+                                            .filter(|(_, message)| message.get_deletion() != Some(Deletion::ForMe))
+                                        {
                                             let sender = self.contacts.get_contact(message.get_sender());
                                             let sender_is_user =
                                                 message.get_sender() == &own_public_key_base_64.clone();
@@ -827,9 +1994,74 @@ impl eframe::App for App {
                                                         .color(egui::Color32::GRAY)
                                                         .size(8.),
                                                     );
+                                                    if let Some(edited_at) = message.get_edited_at() {
+                                                        let history = message
+                                                            .get_previous_versions()
+                                                            .iter()
+                                                            .map(|version| {
+                                                                format!(
+                                                                    "{}: {}",
+                                                                    version.replaced_at.format("%Y-%m-%d %H:%M:%S"),
+                                                                    version.content
+                                                                )
+                                                            })
+                                                            .collect::<Vec<_>>()
+                                                            .join("\n");
+                                                        ui.label(RichText::new("(edited)").color(egui::Color32::GRAY).size(8.))
+                                                            .on_hover_text(format!(
+                                                                "Edited {}\n\nReplaced versions:\n{}",
+                                                                edited_at.format("%Y-%m-%d %H:%M:%S"),
+                                                                history
+                                                            ));
+                                                    }
+                                                    if sender_is_user && message.get_status() == MessageStatus::Pending {
+                                                        ui.label(RichText::new("🕓").color(egui::Color32::GRAY).size(8.))
+                                                            .on_hover_text("Waiting for peers to send the message to");
+                                                    } else if sender_is_user && message.get_status() == MessageStatus::Failed {
+                                                        ui.label(RichText::new("⚠").color(egui::Color32::RED).size(8.))
+                                                            .on_hover_text("Sending failed, right click the message to retry");
+                                                    } else if sender_is_user {
+                                                        let participants = current_chat.get_participants();
+                                                        let is_read_by = |participant: &String| {
+                                                            last_read_indices
+                                                                .get(participant.as_str())
+                                                                .is_some_and(|last_read_index| *last_read_index >= message_index)
+                                                        };
+                                                        let delivery_status = participants
+                                                            .iter()
+                                                            .map(|participant| {
+                                                                let name = match self.contacts.get_contact(participant) {
+                                                                    Some(contact) => contact.name.clone(),
+                                                                    None => participant.clone(),
+                                                                };
+                                                                if is_read_by(participant) {
+                                                                    format!("{}: read", name)
+                                                                } else if message.is_delivered_to(participant) {
+                                                                    format!("{}: delivered", name)
+                                                                } else {
+                                                                    format!("{}: sent", name)
+                                                                }
+                                                            })
+                                                            .collect::<Vec<_>>()
+                                                            .join("\n");
+                                                        let read_by_all = participants.iter().all(is_read_by);
+                                                        let delivered_to_all = participants
+                                                            .iter()
+                                                            .all(|participant| message.is_delivered_to(participant));
+                                                        ui.label(
+                                                            RichText::new(if read_by_all || delivered_to_all { "✔✔" } else { "✔" })
+                                                                .color(if read_by_all {
+                                                                    egui::Color32::LIGHT_BLUE
+                                                                } else {
+                                                                    egui::Color32::GRAY
+                                                                })
+                                                                .size(8.),
+                                                        )
+                                                        .on_hover_text(delivery_status);
+                                                    }
                                                     if let Some(answer_to_id) = message.get_answer_to() {
                                                         if let Some(answer_to_message) = current_chat.get_message_from_id(answer_to_id) {
-                                                            ui.add(Label::new(RichText::new(format!("{} ⮪", answer_to_message.get_content()).to_string()).size(10.)));
+                                                            ui.add(Label::new(RichText::new(format!("{} ⮪", message_text(answer_to_message)).to_string()).size(10.)));
                                                         }
                                                     }
                                                 });
@@ -879,19 +2111,151 @@ impl eframe::App for App {
                                                         }
                                                     }
                                                     let message_label_response = ui.add(
-                                                        Label::new(RichText::new(
-                                                            message.get_content().clone(),
-                                                        ))
+                                                        Label::new(match message.get_attachment() {
+                                                            Some(attachment) => RichText::new(format!(
+                                                                "📄 {} ({})",
+                                                                attachment.name,
+                                                                format_size(attachment.size)
+                                                            )),
+                                                            None if message.get_deletion().is_some() => {
+                                                                RichText::new(message_text(message))
+                                                                    .italics()
+                                                                    .color(egui::Color32::GRAY)
+                                                            }
+                                                            None => RichText::new(message.get_content().clone()),
+                                                        })
                                                         .wrap(true),
                                                     );
+                                                    if let Some(attachment) = message.get_attachment() {
+                                                        match message.get_attachment_path() {
+                                                            Some(path) => {
+                                                                ui.label(RichText::new("🗁").size(10.))
+                                                                    .on_hover_text(path.display().to_string());
+                                                            }
+                                                            None => match self.network.downloads.lock().unwrap().get(&attachment.file_id) {
+                                                                Some(DownloadState::Running(progress)) => {
+                                                                    ui.add(
+                                                                        egui::ProgressBar::new(*progress)
+                                                                            .desired_width(80.)
+                                                                            .show_percentage(),
+                                                                    );
+                                                                }
+                                                                download_state => {
+                                                                    if let Some(DownloadState::Failed(error)) = download_state {
+                                                                        ui.label(RichText::new("⚠").color(egui::Color32::RED))
+                                                                            .on_hover_text(error);
+                                                                    }
+                                                                    if ui.small_button("⬇ Save").clicked() {
+                                                                        download_message_id = Some(*message.get_message_id());
+                                                                    }
+                                                                }
+                                                            },
+                                                        }
+                                                    }
 
                                                     message_label_response.context_menu(|ui| {
+                                                        if message.get_deletion().is_some() {
+                                                            if ui.button("🗑 Delete for me").clicked() {
+                                                                ui.close_menu();
+                                                                delete_message = Some((*message.get_message_id(), Deletion::ForMe));
+                                                            }
+                                                            return;
+                                                        }
                                                         if ui.button("⮪ Answer").clicked() {
                                                             ui.close_menu();
+                                                            self.current_message_edit_of = None;
                                                             self.current_message_answer_to = Some(*message.get_message_id());
                                                         }
+                                                        ui.menu_button("☺ React", |ui| {
+                                                            ui.horizontal(|ui| {
+                                                                for emoji in REACTION_EMOJIS {
+                                                                    if ui.button(emoji).clicked() {
+                                                                        ui.close_menu();
+                                                                        toggle_reaction = Some((*message.get_message_id(), emoji.to_string()));
+                                                                    }
+                                                                }
+                                                            });
+                                                        });
+                                                        if sender_is_user
+                                                            && message.get_attachment().is_none()
+                                                            && ui.button("✏ Edit").clicked()
+                                                        {
+                                                            ui.close_menu();
+                                                            self.current_message_answer_to = None;
+                                                            self.current_message_edit_of = Some(*message.get_message_id());
+                                                            self.current_message = message.get_content().clone();
+                                                        }
+                                                        if message.get_status() == MessageStatus::Failed
+                                                            && ui.button("↻ Retry").clicked()
+                                                        {
+                                                            ui.close_menu();
+                                                            retry_message_id = Some(*message.get_message_id());
+                                                        }
+                                                        ui.separator();
+                                                        if ui.button("🗑 Delete for me").clicked() {
+                                                            ui.close_menu();
+                                                            delete_message = Some((*message.get_message_id(), Deletion::ForMe));
+                                                        }
+                                                        if sender_is_user && ui.button("🗑 Delete for everyone").clicked() {
+                                                            ui.close_menu();
+                                                            delete_message = Some((*message.get_message_id(), Deletion::ForEveryone));
+                                                        }
                                                     });
                                                 });
+                                                if let Some((attachment, thumbnail)) = message.get_attachment().and_then(|attachment| {
+                                                    self.images.get_thumbnail(ctx, attachment).map(|thumbnail| (attachment, thumbnail))
+                                                }) {
+                                                    ui.with_layout(layout, |ui| {
+                                                        if ui
+                                                            .add(
+                                                                egui::Image::new(&thumbnail)
+                                                                    .max_size(vec2(160., 160.))
+                                                                    .sense(egui::Sense::click()),
+                                                            )
+                                                            .on_hover_text("Click to enlarge")
+                                                            .clicked()
+                                                        {
+                                                            self.enlarged_image_message_id = Some(*message.get_message_id());
+                                                            // fetch the full image unless we have it or are already fetching it
+                                                            if message.get_attachment_path().is_none()
+                                                                && !matches!(
+                                                                    self.network.downloads.lock().unwrap().get(&attachment.file_id),
+                                                                    Some(DownloadState::Running(_))
+                                                                )
+                                                            {
+                                                                download_message_id = Some(*message.get_message_id());
+                                                            }
+                                                        }
+                                                    });
+                                                }
+                                                let reactions = current_chat.get_reactions(message.get_message_id());
+                                                if message.get_deletion().is_none() && !reactions.is_empty() {
+                                                    ui.with_layout(layout, |ui| {
+                                                        for (emoji, peers) in reactions {
+                                                            let reacted_by = peers
+                                                                .iter()
+                                                                .map(|peer| {
+                                                                    if peer == &own_public_key_base_64 {
+                                                                        "You".to_string()
+                                                                    } else {
+                                                                        peer_name(&self.contacts, peer)
+                                                                    }
+                                                                })
+                                                                .collect::<Vec<_>>()
+                                                                .join("\n");
+                                                            if ui
+                                                                .selectable_label(
+                                                                    peers.contains(&own_public_key_base_64),
+                                                                    RichText::new(format!("{} {}", emoji, peers.len())).size(10.),
+                                                                )
+                                                                .on_hover_text(reacted_by)
+                                                                .clicked()
+                                                            {
+                                                                toggle_reaction = Some((*message.get_message_id(), emoji));
+                                                            }
+                                                        }
+                                                    });
+                                                }
                                             });
                                             ui.end_row();
                                         }
@@ -911,7 +2275,226 @@ impl eframe::App for App {
                 }
             }
         });
+        if let Some(message_id) = self.enlarged_image_message_id {
+            let mut open = true;
+            let chats = self.chats.lock().unwrap();
+            match self
+                .current_chat_id
+                .and_then(|chat_id| chats.get_chat(&chat_id))
+                .and_then(|chat| chat.get_message_from_id(&message_id))
+                .and_then(|message| {
+                    message
+                        .get_attachment()
+                        .map(|attachment| (message, attachment))
+                }) {
+                Some((message, attachment)) => {
+                    egui::Window::new(attachment.name.clone())
+                        .id(egui::Id::new("image_viewer"))
+                        .open(&mut open)
+                        .collapsible(false)
+                        .show(ctx, |ui| {
+                            let max_size = ctx.screen_rect().size() * 0.8;
+                            match message.get_attachment_path() {
+                                Some(path) => match self.images.get_image(ctx, attachment, path) {
+                                    Some(image) => {
+                                        ui.add(egui::Image::new(&image).max_size(max_size));
+                                    }
+                                    None => {
+                                        ui.label(
+                                            RichText::new("Could not load the image")
+                                                .color(egui::Color32::RED),
+                                        );
+                                    }
+                                },
+                                None => {
+                                    if let Some(thumbnail) =
+                                        self.images.get_thumbnail(ctx, attachment)
+                                    {
+                                        ui.add(egui::Image::new(&thumbnail).max_size(max_size));
+                                    }
+                                    match self
+                                        .network
+                                        .downloads
+                                        .lock()
+                                        .unwrap()
+                                        .get(&attachment.file_id)
+                                    {
+                                        Some(DownloadState::Running(progress)) => {
+                                            ui.add(
+                                                egui::ProgressBar::new(*progress).show_percentage(),
+                                            );
+                                        }
+                                        Some(DownloadState::Failed(error)) => {
+                                            ui.label(
+                                                RichText::new(format!("⚠ {}", error))
+                                                    .color(egui::Color32::RED),
+                                            );
+                                        }
+                                        None => {}
+                                    }
+                                }
+                            }
+                            ui.label(
+                                RichText::new(format_size(attachment.size))
+                                    .color(egui::Color32::GRAY)
+                                    .size(10.),
+                            );
+                        });
+                }
+                None => open = false,
+            }
+            drop(chats);
+            if !open {
+                self.enlarged_image_message_id = None;
+            }
+        }
+        if let (Some(chat_id), Some(message_id), Some(p2pc)) =
+            (self.current_chat_id, download_message_id, &mut self.p2pc)
+        {
+            if let Some(message) = self
+                .chats
+                .lock()
+                .unwrap()
+                .get_chat(&chat_id)
+                .and_then(|chat| chat.get_message_from_id(&message_id))
+            {
+                if let (Some(attachment), Ok(peer)) = (
+                    message.get_attachment(),
+                    libp2p::PeerId::from_str(message.get_sender()),
+                ) {
+                    self.network
+                        .downloads
+                        .lock()
+                        .unwrap()
+                        .insert(attachment.file_id, DownloadState::Running(0.));
+                    p2pc.execute(p2pc_lib::Action::DownloadFile {
+                        peer,
+                        attachment: attachment.clone(),
+                        path: download_path(&self.settings.download_directory, attachment),
+                    })
+                    .ok();
+                }
+            }
+        }
+        if let (Some(chat_id), Some(message_id)) = (self.current_chat_id, retry_message_id) {
+            if let Some(chat) = self.chats.lock().unwrap().get_chat_mut(&chat_id) {
+                chat.retry_message(&message_id);
+            }
+            ctx.request_repaint();
+        }
+        if let (Some(chat_id), Some((message_id, emoji)), Some(p2pc)) =
+            (self.current_chat_id, toggle_reaction, &mut self.p2pc)
+        {
+            if let Some(chat) = self.chats.lock().unwrap().get_chat_mut(&chat_id) {
+                chat.toggle_reaction(p2pc, &own_public_key_base_64, &message_id, emoji);
+            }
+            ctx.request_repaint();
+        }
+        if let (Some(chat_id), Some((message_id, deletion)), Some(p2pc)) =
+            (self.current_chat_id, delete_message, &mut self.p2pc)
+        {
+            if let Some(chat) = self.chats.lock().unwrap().get_chat_mut(&chat_id) {
+                match deletion {
+                    Deletion::ForMe => chat.delete_message_for_me(&message_id),
+                    Deletion::ForEveryone => chat.retract_message(p2pc, &message_id),
+                }
+            }
+            if self.current_message_edit_of == Some(message_id) {
+                self.current_message_edit_of = None;
+                self.current_message.clear();
+            }
+            if self.current_message_answer_to == Some(message_id) {
+                self.current_message_answer_to = None;
+            }
+            if self.enlarged_image_message_id == Some(message_id) {
+                self.enlarged_image_message_id = None;
+            }
+            ctx.request_repaint();
+        }
+    }
+}
+
+/// Name of the contact with the peer id, or the start of the peer id for unknown peers.
+fn peer_name(contacts: &Contacts, peer: &str) -> String {
+    match contacts.get_contact(peer) {
+        Some(contact) => contact.name.clone(),
+        None if peer.len() > 12 => format!("{}...", &peer[..12]),
+        None => peer.to_string(),
+    }
+}
+
+/// Text of a message as shown in references to it, e.g. in answers.
+fn message_text(message: &chat::Message) -> String {
+    if message.get_deletion().is_some() {
+        "🗑 This message was deleted".to_string()
+    } else {
+        message.get_content().clone()
+    }
+}
+
+/// Color and description of the presence of a peer.
+fn presence_status(
+    network: &NetworkState,
+    peer: &str,
+    last_seen: Option<&chrono::DateTime<chrono::Local>>,
+) -> (egui::Color32, String) {
+    match network.get_presence(peer) {
+        Some(p2pc_lib::Presence::Online) => (egui::Color32::GREEN, "Online".to_string()),
+        Some(p2pc_lib::Presence::Away) => (egui::Color32::YELLOW, "Away".to_string()),
+        None => match last_seen {
+            Some(last_seen) => (
+                egui::Color32::GRAY,
+                format!("Last seen {}", last_seen.format("%Y-%m-%d %H:%M")),
+            ),
+            None => (egui::Color32::GRAY, "Offline".to_string()),
+        },
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn default_download_directory() -> std::path::PathBuf {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| std::path::PathBuf::from(home).join("Downloads"))
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+}
+
+/// Picks a path in the download directory that does not exist yet. Only the file name of the
+/// attachment is used, so peers cannot make us write outside of the download directory.
+fn download_path(
+    download_directory: &str,
+    attachment: &p2pc_lib::Attachment,
+) -> std::path::PathBuf {
+    let directory = if download_directory.trim().is_empty() {
+        default_download_directory()
+    } else {
+        std::path::PathBuf::from(download_directory.trim())
+    };
+    let file_name = std::path::Path::new(&attachment.name)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_else(|| attachment.file_id.to_string());
+    let mut path = directory.join(&file_name);
+    let mut index = 1;
+    while path.exists() {
+        path = directory.join(format!("({}) {}", index, file_name));
+        index += 1;
     }
+    path
 }
 
 fn setup_custom_fonts(ctx: &egui::Context) {